@@ -1,9 +1,10 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{env, num::NonZeroUsize, path::PathBuf, str::FromStr};
 
 // TODO(gib): generate zsh completions (in build.rs?).
 // https://github.com/sharkdp/fd/blob/master/build.rs
 // https://github.com/TeXitoi/structopt/blob/d1a50bf204970bccd55a0351a114fc8e05c854ce/examples/gen_completions.rs
 use anyhow::{anyhow, Result};
+use chrono::Utc;
 use serde_derive::{Deserialize, Serialize};
 use slog::Level;
 use structopt::{
@@ -63,6 +64,28 @@ pub struct Args {
     pub(crate) config: String,
     #[structopt(subcommand)]
     pub(crate) cmd: Option<SubCommand>,
+    /// When this run started, used to name its log file and task log directory.
+    #[structopt(skip = Utc::now().to_rfc3339())]
+    pub start_time: String,
+}
+
+impl Args {
+    /// Name of this run's log file (without the `.log` extension), which its
+    /// task log directory is named after.
+    #[must_use]
+    pub fn log_name(&self) -> String {
+        format!("up-rs_{}", self.start_time)
+    }
+
+    /// Directory to write log files to, or `None` if file logging is disabled.
+    #[must_use]
+    pub fn file_log_dir(&self) -> Option<PathBuf> {
+        match &self.log_dir {
+            Some(p) if p == &PathBuf::new() => None,
+            Some(p) => Some(p.clone()),
+            None => Some(env::temp_dir().join("up-rs/logs")),
+        }
+    }
 }
 
 fn from_level(level: &str) -> Result<Level> {
@@ -82,12 +105,46 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    /// Which task output to show while tasks are running.
+    /// Full: Show stdout and stderr.
+    /// Errors: Only show stderr.
+    #[derive(Debug, Clone, Copy)]
+    pub enum TaskOutput {
+        Full,
+        Errors,
+    }
+}
+
+arg_enum! {
+    /// How to print the task graph.
+    /// Tree: Indented text tree.
+    /// Dot: Graphviz DOT format, e.g. for `up graph --format=dot | dot -Tsvg`.
+    #[derive(Debug, Clone, Copy)]
+    pub enum GraphFormat {
+        Tree,
+        Dot,
+    }
+}
+
+// Can't use `#[default]` inside `arg_enum!`.
+#[allow(clippy::derivable_impls)]
+impl Default for TaskOutput {
+    fn default() -> Self {
+        Self::Errors
+    }
+}
+
 // Optional subcommand (e.g. the "link" in "up link").
 #[derive(Debug, StructOpt)]
 pub(crate) enum SubCommand {
     /// Run the update scripts. If you don't provide a subcommand this is the default action.
     /// If you want to pass Run args you will need to specify the subcommand.
     Run(RunOptions),
+    /// List the tasks defined in your config.
+    List(ListOptions),
+    /// Show which tasks require which, and the bootstrap order.
+    Graph(GraphOptions),
     // TODO(gib): Work out how to do clap's help and long_help in structopt.
     /// Symlink your dotfiles from a git repo to your home directory.
     Link(LinkOptions),
@@ -103,6 +160,7 @@ pub(crate) enum SubCommand {
 }
 
 #[derive(Debug, StructOpt, Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub(crate) struct RunOptions {
     /// Run the bootstrap list of tasks in series first, then run the rest in
     /// parallel. Designed for first-time setup.
@@ -121,9 +179,53 @@ pub(crate) struct RunOptions {
     /// tasks.
     #[structopt(long)]
     pub(crate) tasks: Option<Vec<String>>,
+    /// Don't make any changes, just run the check commands and print which tasks
+    /// would be run (with their expanded run commands) and which would be skipped.
+    #[structopt(long)]
+    pub(crate) dry_run: bool,
+    /// Maximum number of tasks to run at the same time, overrides `max_parallel`
+    /// in the up.toml. Use `--jobs 1` to run tasks one at a time.
+    #[structopt(long, short = "j")]
+    pub(crate) jobs: Option<NonZeroUsize>,
+    /// Which task output to show as tasks run, each line is prefixed with the
+    /// task name. If only one task is running its output goes directly to the
+    /// terminal, unless the live progress display is shown.
+    #[structopt(long, default_value = "errors", possible_values = &TaskOutput::variants(), case_insensitive = true)]
+    pub(crate) task_output: TaskOutput,
+    /// Write a JSON report of the run to this path, with the result of each
+    /// task that was run or checked. It is also written if the run fails
+    /// before any task starts, with the error.
+    #[structopt(long)]
+    pub(crate) report: Option<PathBuf>,
+    /// Stop the run as soon as any task fails: no more tasks are started, and
+    /// the commands of running tasks are terminated. By default this only
+    /// happens if a task with `critical = true` fails.
+    #[structopt(long, conflicts_with = "keep-going")]
+    pub(crate) fail_fast: bool,
+    /// Keep running the rest of the tasks when a task fails, even if it is a
+    /// `critical` task.
+    #[structopt(long)]
+    pub(crate) keep_going: bool,
+    /// Run tasks even if they passed within their `run_interval`.
+    #[structopt(long)]
+    pub(crate) force: bool,
 }
 
-#[derive(Debug, StructOpt, Default, Serialize, Deserialize)]
+#[derive(Debug, StructOpt, Default)]
+pub(crate) struct ListOptions {
+    /// Print the tasks as JSON rather than a table.
+    #[structopt(long)]
+    pub(crate) json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct GraphOptions {
+    /// Output format.
+    #[structopt(long, default_value = "tree", possible_values = &GraphFormat::variants(), case_insensitive = true)]
+    pub(crate) format: GraphFormat,
+}
+
+#[derive(Debug, Clone, StructOpt, Default, Serialize, Deserialize)]
 pub(crate) struct LinkOptions {
     /// Path where your dotfiles are kept (hopefully in source control).
     #[structopt(short = "f", long = "from", default_value = "~/code/dotfiles")]
@@ -165,7 +267,7 @@ pub(crate) struct GenerateOptions {
     pub(crate) lib: Option<GenerateLib>,
 }
 
-#[derive(Debug, StructOpt, Serialize, Deserialize)]
+#[derive(Debug, Clone, StructOpt, Serialize, Deserialize)]
 pub(crate) struct UpdateSelfOptions {
     /// URL to download update from.
     #[structopt(long, default_value = SELF_UPDATE_URL)]
@@ -195,7 +297,7 @@ pub(crate) enum GenerateLib {
     Defaults(GenerateDefaultsConfig),
 }
 
-#[derive(Debug, StructOpt, Serialize, Deserialize)]
+#[derive(Debug, Clone, StructOpt, Serialize, Deserialize)]
 pub struct GenerateGitConfig {
     /// Path to toml file to update.
     #[structopt(long, parse(from_str))]
@@ -1,18 +1,27 @@
 //! Manages the config files (default location ~/.config/up/).
 
 use std::{
-    collections::HashMap,
-    env, fs,
+    collections::{BTreeMap, HashMap},
+    env, fmt, fs,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use log::{debug, info, trace};
+use serde::{
+    de::{
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        MapAccess, SeqAccess, Visitor,
+    },
+    Deserialize as _, Deserializer,
+};
 use serde_derive::{Deserialize, Serialize};
 
 use crate::{
-    args::{Args, GitOptions, RunOptions, SubCommand},
+    args::{Args, GitOptions, RunOptions, SubCommand, TaskOutput},
     git,
+    tasks::task::TaskConfig,
 };
 
 #[derive(Default, Debug)]
@@ -21,6 +30,30 @@ pub struct UpConfig {
     pub config_toml: ConfigToml,
     pub bootstrap: bool,
     pub tasks: Option<Vec<String>>,
+    pub dry_run: bool,
+    pub jobs: Option<NonZeroUsize>,
+    pub task_output: TaskOutput,
+    /// Directory to write the output of each task's commands to for this run,
+    /// or `None` if file logging is disabled.
+    pub task_log_dir: Option<PathBuf>,
+    /// Path to write a JSON report of the run to.
+    pub report: Option<PathBuf>,
+    /// What to do when a task fails.
+    pub on_failure: OnFailure,
+    /// Whether to ignore tasks' `run_interval`.
+    pub force: bool,
+}
+
+/// What to do when a task fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnFailure {
+    /// Stop the run if the task is `critical`, otherwise keep going.
+    #[default]
+    StopIfCritical,
+    /// Stop the run (`--fail-fast`).
+    Stop,
+    /// Keep running the other tasks (`--keep-going`).
+    KeepGoing,
 }
 
 // TODO(gib): Work out the data structure for the toml files.
@@ -39,12 +72,63 @@ pub struct ConfigToml {
     /// Environment variables to inherit from running env, doesn't error if not
     /// defined.
     pub inherit_env: Option<Vec<String>>,
-    // TODO(gib): handle sudo requirements more granularly.
-    /// Set to true to prompt for sudo privileges before running.
+    /// Default `needs_sudo` for tasks, set to true if every task needs
+    /// superuser privileges.
     #[serde(default = "default_false")]
     pub needs_sudo: bool,
+    /// How often in seconds to refresh superuser privileges during the run,
+    /// so they don't expire. Default is 60.
+    pub sudo_refresh_interval: Option<u64>,
     /// List of tasks to run in order in bootstrap mode.
     pub bootstrap_tasks: Option<Vec<String>>,
+    /// Maximum number of tasks to run at the same time. Default is no limit.
+    /// Overridden by `up run --jobs`.
+    pub max_parallel: Option<NonZeroUsize>,
+    /// Default timeout in seconds for task commands. Tasks can override this
+    /// with their own `timeout`.
+    pub timeout: Option<u64>,
+    /// Directory to look for `up-<name>` `run_lib` plugins in before the `PATH`,
+    /// relative to the up.toml.
+    pub plugin_dir: Option<String>,
+    /// Tasks defined here rather than in their own files in the tasks
+    /// directory, either as a `[[tasks]]` array or as `[tasks.<name>]` tables.
+    pub tasks: Option<InlineTasks>,
+}
+
+/// Tasks defined inline in the up.toml, with the same fields as a task file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum InlineTasks {
+    /// `[[tasks]]` entries, each of which must set a `name`.
+    List(Vec<TaskConfig>),
+    /// `[tasks.<name>]` tables, named by their key unless they set a `name`.
+    Named(BTreeMap<String, TaskConfig>),
+}
+
+// Not `#[serde(untagged)]`, as that hides the error for e.g. an unknown field
+// in one of the tasks.
+impl<'de> serde::Deserialize<'de> for InlineTasks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct InlineTasksVisitor;
+
+        impl<'de> Visitor<'de> for InlineTasksVisitor {
+            type Value = InlineTasks;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of tasks, or a table of tasks by name")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(InlineTasks::List)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                BTreeMap::deserialize(MapAccessDeserializer::new(map)).map(InlineTasks::Named)
+            }
+        }
+
+        deserializer.deserialize_any(InlineTasksVisitor)
+    }
 }
 
 const fn default_false() -> bool {
@@ -56,6 +140,10 @@ impl UpConfig {
     pub fn from(args: Args) -> Result<Self> {
         let mut config_toml = ConfigToml::default();
 
+        let task_log_dir = args
+            .file_log_dir()
+            .map(|log_dir| log_dir.join(format!("{}_tasks", args.log_name())));
+
         let run_options = match args.cmd {
             Some(SubCommand::Run(run_options)) => run_options,
             _ => RunOptions::default(),
@@ -99,12 +187,24 @@ impl UpConfig {
         };
 
         let bootstrap = run_options.bootstrap;
+        let on_failure = match (run_options.fail_fast, run_options.keep_going) {
+            (true, _) => OnFailure::Stop,
+            (false, true) => OnFailure::KeepGoing,
+            (false, false) => OnFailure::StopIfCritical,
+        };
 
         Ok(Self {
             up_toml_path,
             config_toml,
             bootstrap,
             tasks: run_options.tasks,
+            dry_run: run_options.dry_run,
+            jobs: run_options.jobs,
+            task_output: run_options.task_output,
+            task_log_dir,
+            report: run_options.report,
+            on_failure,
+            force: run_options.force,
         })
     }
 
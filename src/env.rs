@@ -12,7 +12,16 @@ pub fn get_env(
     inherit_env: Option<&Vec<String>>,
     input_env: Option<&HashMap<String, String>>,
 ) -> Result<HashMap<String, String>> {
-    let mut env: HashMap<String, String> = HashMap::new();
+    extend_env(HashMap::new(), inherit_env, input_env)
+}
+
+/// Add the `inherit_env` and `input_env` vars to an existing `env`. Values in
+/// `input_env` can refer to vars in `env` (including the ones they override).
+pub fn extend_env(
+    mut env: HashMap<String, String>,
+    inherit_env: Option<&Vec<String>>,
+    input_env: Option<&HashMap<String, String>>,
+) -> Result<HashMap<String, String>> {
     if let Some(inherited_env) = inherit_env {
         for inherited_var in inherited_env {
             if let Ok(value) = std::env::var(inherited_var) {
@@ -28,6 +28,7 @@ pub mod args;
 mod config;
 mod env;
 mod generate;
+pub mod progress;
 pub mod tasks;
 pub mod update;
 
@@ -43,6 +44,7 @@ pub mod update;
 ///
 /// [Args]: crate::args::Args
 pub fn run(args: Args) -> Result<()> {
+    progress::init(&args.color);
     match args.cmd {
         // TODO(gib): Handle multiple link directories both as args and in config.
         // TODO(gib): Add option to warn instead of failing if there are conflicts.
@@ -74,6 +76,16 @@ pub fn run(args: Args) -> Result<()> {
                 generate::run(&config)?;
             }
         },
+        Some(SubCommand::List(ref opts)) => {
+            let json = opts.json;
+            let config = UpConfig::from(args)?;
+            tasks::list::run(&config, "tasks", json)?;
+        }
+        Some(SubCommand::Graph(ref opts)) => {
+            let format = opts.format;
+            let config = UpConfig::from(args)?;
+            tasks::graph::run(&config, "tasks", format)?;
+        }
         Some(SubCommand::Run(ref _opts)) => {
             // TODO(gib): Store and fetch config in config module.
             let config = UpConfig::from(args)?;
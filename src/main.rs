@@ -25,12 +25,11 @@ use std::{
 };
 
 use anyhow::{bail, Result};
-use chrono::Utc;
 use displaydoc::Display;
 use log::{info, trace};
 use slog::{o, Drain, Duplicate, FnValue, LevelFilter, Logger};
 use thiserror::Error;
-use up_rs::args::Color;
+use up_rs::{args::Color, progress::ProgressDecorator};
 
 fn main() -> Result<()> {
     // Get starting time.
@@ -48,7 +47,8 @@ fn main() -> Result<()> {
     }
     .build();
 
-    let stderr_drain = slog_term::CompactFormat::new(stderr_decorator)
+    // Print log messages above the live progress display, if it's shown.
+    let stderr_drain = slog_term::CompactFormat::new(ProgressDecorator(stderr_decorator))
         .build()
         .fuse();
     let stderr_async_drain = slog_async::Async::new(stderr_drain).build().fuse();
@@ -57,19 +57,14 @@ fn main() -> Result<()> {
     // TODO(gib): Update --help text to note how you set the log level.
     let stderr_level_filter = LevelFilter::new(stderr_async_drain, args.log_level);
 
-    let should_log_to_file = match &args.log_dir {
-        Some(p) if p == &PathBuf::new() => false,
-        Some(_) | None => true,
-    };
-
     let mut file_drain = None;
     let mut log_paths_opt = None;
-    if should_log_to_file {
+    if let Some(log_dir) = args.file_log_dir() {
         let LogPaths {
             log_path,
             log_path_link,
             log_file,
-        } = get_log_path_file(args.log_dir.as_ref())
+        } = get_log_path_file(log_dir, &args.log_name())
             .map_err(|e| MainError::LogFileSetupFailed { source: e })?;
         log_paths_opt = Some((log_path, log_path_link));
 
@@ -135,17 +130,16 @@ struct LogPaths {
     log_path_link: PathBuf,
 }
 
-/// Create log file, and a symlink to it that can be used to find the latest
-/// one.
-fn get_log_path_file(log_dir: Option<&PathBuf>) -> Result<LogPaths> {
-    let log_dir = log_dir.map_or_else(|| env::temp_dir().join("up-rs/logs"), |p| p.to_path_buf());
+/// Create log file `<log_name>.log`, and a symlink to it that can be used to
+/// find the latest one.
+fn get_log_path_file(log_dir: PathBuf, log_name: &str) -> Result<LogPaths> {
     fs::create_dir_all(&log_dir).map_err(|e| MainError::CreateDirError {
         path: log_dir.clone(),
         source: e,
     })?;
     let log_path_link = log_dir.as_path().join("up-rs_latest.log");
     let mut log_path = log_dir;
-    log_path.push(format!("up-rs_{}.log", Utc::now().to_rfc3339()));
+    log_path.push(format!("{}.log", log_name));
 
     // Delete symlink if it exists, or is a broken symlink.
     if log_path_link.exists() || log_path_link.symlink_metadata().is_ok() {
@@ -0,0 +1,293 @@
+//! Live progress display for running tasks, shown on stderr if it is a
+//! terminal.
+//!
+//! While the display is shown log messages are printed above it, see
+//! [`ProgressDecorator`]. If stderr isn't a terminal (or `--color never` is
+//! passed) nothing is drawn and we fall back to plain line logging.
+
+// Indicatif templates look like format strings.
+#![allow(clippy::literal_string_with_formatting_args)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use slog::{OwnedKVList, Record};
+use slog_term::{Decorator, RecordDecorator};
+
+use crate::{args::Color, tasks::task::TaskStatus};
+
+/// Whether the live display can be shown.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The display currently being shown, if any.
+static ACTIVE: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// How often to redraw spinners and elapsed times.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Enable the live display if stderr is a terminal and colours aren't turned
+/// off.
+pub(crate) fn init(color: &Color) {
+    let enabled = !matches!(color, Color::Never) && io::stderr().is_terminal();
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// The display currently being shown, for adding more progress bars to.
+pub(crate) fn active() -> Option<MultiProgress> {
+    ACTIVE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Run `f` with the display hidden, so it can write to stderr.
+pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    match active() {
+        Some(multi) => multi.suspend(f),
+        None => f(),
+    }
+}
+
+/// Slog decorator that writes log messages above the live display.
+#[derive(Debug)]
+pub struct ProgressDecorator<D>(pub D);
+
+impl<D: Decorator> Decorator for ProgressDecorator<D> {
+    fn with_record<F>(&self, record: &Record, logger_values: &OwnedKVList, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut dyn RecordDecorator) -> io::Result<()>,
+    {
+        suspend(|| self.0.with_record(record, logger_values, f))
+    }
+}
+
+/// State of a task shown in the display, following its [`TaskStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BarState {
+    New,
+    Blocked,
+    Queued,
+    Running,
+    Skipped,
+    Passed,
+    Failed,
+}
+
+impl BarState {
+    /// Message shown next to the task name.
+    const fn message(self) -> &'static str {
+        match self {
+            Self::New => "new",
+            Self::Blocked => "blocked",
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Skipped => "skipped",
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl From<&TaskStatus> for BarState {
+    fn from(status: &TaskStatus) -> Self {
+        match status {
+            TaskStatus::New => Self::New,
+            TaskStatus::Blocked => Self::Blocked,
+            TaskStatus::Queued => Self::Queued,
+            TaskStatus::Running => Self::Running,
+            TaskStatus::Skipped => Self::Skipped,
+            TaskStatus::Passed => Self::Passed,
+            TaskStatus::Failed(_) => Self::Failed,
+        }
+    }
+}
+
+/// Live display of the state of each task in a run.
+#[derive(Debug)]
+pub(crate) struct TasksProgress {
+    multi: MultiProgress,
+    /// Each task's progress bar, and the state it is showing.
+    bars: BTreeMap<String, (ProgressBar, BarState)>,
+    /// Whether the display is cleared while a task uses the terminal.
+    paused: bool,
+}
+
+impl TasksProgress {
+    /// Start showing the display for `names`, or return `None` if it isn't
+    /// enabled.
+    pub(crate) fn new(names: &BTreeSet<String>) -> Option<Self> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(Self::with_draw_target(names, ProgressDrawTarget::stderr()))
+    }
+
+    fn with_draw_target(names: &BTreeSet<String>, target: ProgressDrawTarget) -> Self {
+        let multi = MultiProgress::with_draw_target(target);
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+        let mut bars = BTreeMap::new();
+        for name in names {
+            let bar = multi.add(ProgressBar::new_spinner());
+            bar.set_style(waiting_style());
+            bar.set_prefix(format!("{:width$}", name, width = width));
+            bar.set_message(BarState::New.message());
+            bars.insert(name.clone(), (bar, BarState::New));
+        }
+        *ACTIVE.lock().unwrap_or_else(PoisonError::into_inner) = Some(multi.clone());
+        Self {
+            multi,
+            bars,
+            paused: false,
+        }
+    }
+
+    /// Clear the display and stop drawing it, so a task can use the terminal
+    /// directly, until [`resume()`](Self::resume) is called.
+    pub(crate) fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.paused = true;
+        // Best effort, at worst the task's output starts below the display.
+        let _ = self.multi.clear();
+        self.multi.set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    /// Start drawing the display again after [`pause()`](Self::pause).
+    pub(crate) fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        self.paused = false;
+        self.multi.set_draw_target(ProgressDrawTarget::stderr());
+    }
+
+    /// Show that task `name` is now in `state`.
+    pub(crate) fn set_state(&mut self, name: &str, state: BarState) {
+        let (bar, shown) = match self.bars.get_mut(name) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if *shown == state {
+            return;
+        }
+        *shown = state;
+        bar.set_message(state.message());
+        match state {
+            BarState::Running => {
+                bar.set_style(running_style());
+                bar.reset_elapsed();
+                bar.enable_steady_tick(TICK_INTERVAL);
+            }
+            BarState::Passed | BarState::Failed | BarState::Skipped => {
+                bar.set_style(finished_style(state));
+                bar.disable_steady_tick();
+                bar.tick();
+            }
+            BarState::New | BarState::Blocked | BarState::Queued => {
+                bar.set_style(waiting_style());
+            }
+        }
+    }
+}
+
+impl Drop for TasksProgress {
+    fn drop(&mut self) {
+        *ACTIVE.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        // Leave the final state of each task on the screen.
+        for (bar, _) in self.bars.values() {
+            bar.disable_steady_tick();
+            bar.abandon();
+        }
+    }
+}
+
+fn waiting_style() -> ProgressStyle {
+    style("  {prefix} {msg:.dim}")
+}
+
+fn running_style() -> ProgressStyle {
+    style("{spinner:.cyan} {prefix} {msg:.cyan} {elapsed}")
+}
+
+fn finished_style(state: BarState) -> ProgressStyle {
+    match state {
+        BarState::Passed => style("  {prefix} {msg:.green}"),
+        BarState::Failed => style("  {prefix} {msg:.red}"),
+        _ => style("  {prefix} {msg:.yellow}"),
+    }
+}
+
+/// Bar for fetching a git repo, added to the live display if it is shown.
+pub(crate) fn fetch_bar(name: &str) -> Option<ProgressBar> {
+    let multi = active()?;
+    let bar = multi.add(ProgressBar::new(0));
+    bar.set_style(style("  {prefix} {msg} [{bar:20}] {pos}/{len} objects"));
+    bar.set_prefix(name.to_owned());
+    bar.set_message("fetching");
+    Some(bar)
+}
+
+fn style(template: &str) -> ProgressStyle {
+    // Templates are constant, so this only fails if one of them is wrong.
+    ProgressStyle::with_template(template).unwrap_or_else(|_| ProgressStyle::default_spinner())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tasks_progress_tests {
+    use std::collections::BTreeSet;
+
+    use indicatif::ProgressDrawTarget;
+
+    use super::{BarState, TasksProgress};
+    use crate::args::Color;
+
+    /// Each task's bar should follow the states it is set to.
+    #[test]
+    fn set_state() {
+        let names: BTreeSet<String> = ["a", "bb"].iter().map(|&s| s.to_owned()).collect();
+        let mut progress = TasksProgress::with_draw_target(&names, ProgressDrawTarget::hidden());
+        let shown = |progress: &TasksProgress, name: &str| {
+            let (bar, state) = &progress.bars[name];
+            (bar.message(), *state)
+        };
+        assert_eq!(shown(&progress, "a"), ("new".to_owned(), BarState::New));
+        assert_eq!(progress.bars["bb"].0.prefix(), "bb");
+        assert_eq!(progress.bars["a"].0.prefix(), "a ");
+
+        for state in [
+            BarState::Blocked,
+            BarState::Queued,
+            BarState::Running,
+            BarState::Running,
+            BarState::Failed,
+        ] {
+            progress.set_state("a", state);
+            assert_eq!(shown(&progress, "a"), (state.message().to_owned(), state));
+        }
+        assert_eq!(shown(&progress, "bb"), ("new".to_owned(), BarState::New));
+
+        // Tasks that aren't shown are ignored.
+        progress.set_state("c", BarState::Passed);
+        assert_eq!(progress.bars.len(), 2);
+    }
+
+    /// Without the display nothing should be shown, so log messages are
+    /// printed as normal.
+    #[test]
+    fn disabled() {
+        super::init(&Color::Never);
+        let names: BTreeSet<String> = ["a".to_owned()].iter().cloned().collect();
+        assert!(TasksProgress::new(&names).is_none());
+        assert_eq!(super::suspend(|| 1), 1);
+    }
+}
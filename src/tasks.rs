@@ -1,22 +1,43 @@
 use std::{
-    collections::{HashMap, HashSet},
-    io,
-    path::PathBuf,
-    process::Command,
-    thread, time,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
 use displaydoc::Display;
 use log::{debug, error, info, trace, warn};
 use thiserror::Error;
 
 use self::TasksError as E;
-use crate::{config, env::get_env};
+use crate::{
+    config::{self, OnFailure},
+    env::get_env,
+    progress::{BarState, TasksProgress},
+    tasks::{
+        state::State,
+        task::{CommandType, TERMINATE_GRACE_PERIOD},
+    },
+};
 
+pub mod constraints;
 pub mod defaults;
 pub mod git;
+pub mod graph;
 pub mod link;
+pub mod list;
+pub mod plugin;
+pub mod report;
+pub mod run_lib;
+pub mod state;
+pub mod sudo;
 pub mod task;
 pub mod update_self;
 
@@ -38,35 +59,55 @@ pub trait ResolveEnv {
 pub enum TaskError {
     #[error("Env lookup error, please define '{}' in your up.toml:", var)]
     ResolveEnv { var: String, source: anyhow::Error },
+    #[error(
+        "Task '{}' has a check table but no check_cmd, so the check would never run.",
+        name
+    )]
+    CheckWithoutCheckCmd { name: String },
+    #[error("Task '{}' check has an invalid stdout_regex '{}'.", name, regex)]
+    InvalidCheckRegex {
+        name: String,
+        regex: String,
+        source: regex::Error,
+    },
+}
+
+/// Returns a function that expands env vars (and `~`) in a string using `env`.
+pub(crate) fn env_fn(env: &HashMap<String, String>) -> impl Fn(&str) -> Result<String> + '_ {
+    // TODO(gib): Allow vars to refer to other vars, detect cycles (topologically
+    // sort inputs).
+    move |s: &str| {
+        let out = shellexpand::full_with_context(s, dirs::home_dir, |k| {
+            env.get(k)
+                .ok_or_else(|| anyhow!("Value not found"))
+                .map(Some)
+        })
+        .map(std::borrow::Cow::into_owned)
+        .map_err(|e| TaskError::ResolveEnv {
+            var: e.var_name,
+            source: e.cause,
+        })?;
+
+        Ok(out)
+    }
 }
 
 /// Run a set of tasks specified in a subdir of the directory containing the up
 /// config.
 pub fn run(config: &config::UpConfig, tasks_dirname: &str) -> Result<()> {
-    // TODO(gib): Handle missing dir & move into config.
-    let mut tasks_dir = config.up_toml_path.as_ref().ok_or(E::None {})?.clone();
-    tasks_dir.pop();
-    tasks_dir.push(tasks_dirname);
+    let tasks_dir = tasks_dir(config, tasks_dirname)?;
 
     let env = get_env(
         config.config_toml.inherit_env.as_ref(),
         config.config_toml.env.as_ref(),
     )?;
 
-    if config.config_toml.needs_sudo {
-        // TODO(gib): this only lasts for 5 minutes.
-        debug!("Prompting for superuser privileges with 'sudo -v'");
-        Command::new("sudo").arg("-v").output()?;
-    }
-
     // If in macOS, don't let the display sleep until the command exits.
     #[cfg(target_os = "macos")]
-    Command::new("caffeinate")
+    std::process::Command::new("caffeinate")
         .args(&["-ds", "-w", &std::process::id().to_string()])
         .spawn()?;
 
-    // TODO(gib): Handle and filter by constraints.
-
     let mut bootstrap_tasks = match (config.bootstrap, &config.config_toml.bootstrap_tasks) {
         (false, _) => Ok(Vec::new()),
         (true, None) => Err(anyhow!(
@@ -76,20 +117,194 @@ pub fn run(config: &config::UpConfig, tasks_dirname: &str) -> Result<()> {
     }?;
     bootstrap_tasks.reverse();
 
-    let filter_tasks_set: Option<HashSet<String>> =
-        config.tasks.clone().map(|v| v.into_iter().collect());
+    let tasks = read_run_tasks(config, &tasks_dir).map_err(|e| report_error(config, e))?;
+
+    if let Some(task_log_dir) = &config.task_log_dir {
+        info!("Writing task logs to {}", task_log_dir.display());
+        fs::create_dir_all(task_log_dir).map_err(|e| E::CreateDir {
+            path: task_log_dir.clone(),
+            source: e,
+        })?;
+        link_latest_task_log_dir(task_log_dir);
+    }
+
+    run_tasks(bootstrap_tasks, tasks, &env, config, &tasks_dir)
+}
 
-    #[allow(clippy::filter_map)]
+/// Load the tasks for a run, applying the defaults from the up config. Fails if any task is
+/// invalid, even one that wouldn't be run.
+fn read_run_tasks(
+    config: &config::UpConfig,
+    tasks_dir: &Path,
+) -> Result<HashMap<String, task::Task>> {
     let mut tasks: HashMap<String, task::Task> = HashMap::new();
+    for (_, task) in load_tasks(config, tasks_dir)? {
+        let mut task = task?;
+        task.config.timeout = task.config.timeout.or(config.config_toml.timeout);
+        if config.config_toml.needs_sudo {
+            task.config.needs_sudo = task.config.needs_sudo.or(Some(true));
+        }
+        tasks.insert(task.name.clone(), task);
+    }
+
+    debug!("Task count: {:?}", tasks.len());
+    trace!("Task list: {:#?}", tasks);
+    Ok(tasks)
+}
+
+/// Write the `--report` (if requested) for a run that failed with `error` before any task was
+/// started, and return the error.
+fn report_error(config: &config::UpConfig, error: anyhow::Error) -> anyhow::Error {
+    if let Some(report_path) = &config.report {
+        if let Err(e) = report::write(
+            report_path,
+            &HashMap::new(),
+            &[],
+            config.dry_run,
+            Some(&error),
+        ) {
+            warn!("{:?}", e);
+        }
+    }
+    error
+}
+
+/// Point the `up-rs_latest_tasks` symlink next to `task_log_dir` at it, like the
+/// `up-rs_latest.log` symlink for the run's log file. Failing to doesn't fail
+/// the run.
+fn link_latest_task_log_dir(task_log_dir: &Path) {
+    let (log_dir, dir_name) = match (task_log_dir.parent(), task_log_dir.file_name()) {
+        (Some(log_dir), Some(dir_name)) => (log_dir, dir_name),
+        _ => return,
+    };
+    let link = log_dir.join("up-rs_latest_tasks");
+    if link
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    {
+        if let Err(e) = fs::remove_file(&link) {
+            warn!("Failed to remove '{}': {}", link.display(), e);
+            return;
+        }
+    }
+    // Relative, so the link still works if the log dir is moved.
+    if let Err(e) = std::os::unix::fs::symlink(dir_name, &link) {
+        warn!(
+            "Failed to link '{}' to the task logs: {}",
+            link.display(),
+            e
+        );
+    }
+}
+
+/// Path to the `tasks_dirname` directory next to the up config.
+pub(crate) fn tasks_dir(config: &config::UpConfig, tasks_dirname: &str) -> Result<PathBuf> {
+    // TODO(gib): Handle missing dir & move into config.
+    let mut tasks_dir = config.up_toml_path.as_ref().ok_or(E::None {})?.clone();
+    tasks_dir.pop();
+    tasks_dir.push(tasks_dirname);
+    Ok(tasks_dir)
+}
+
+/// Read the tasks in `tasks_dir` followed by the ones defined inline in the up
+/// config. A task with the same name as an earlier one is an error. The tasks
+/// dir doesn't have to exist if every task is defined inline.
+pub(crate) fn load_tasks(
+    config: &config::UpConfig,
+    tasks_dir: &Path,
+) -> Result<Vec<(PathBuf, Result<task::Task>)>> {
+    let mut tasks: Vec<(String, PathBuf, Result<task::Task>)> =
+        if config.config_toml.tasks.is_some() && !tasks_dir.exists() {
+            Vec::new()
+        } else {
+            read_tasks(tasks_dir)?
+                .into_iter()
+                .map(|(path, task)| (format!("'{}'", path.display()), path, task))
+                .collect()
+        };
+    tasks.extend(inline_tasks(config));
+
+    let mut defined_in: HashMap<String, String> = HashMap::new();
+    for (location, _, task) in &mut tasks {
+        let name = match task {
+            Ok(task) => task.name.clone(),
+            Err(_) => continue,
+        };
+        if let Some(first) = defined_in.get(&name) {
+            *task = Err(E::DuplicateTask {
+                name,
+                first: first.clone(),
+                second: location.clone(),
+            }
+            .into());
+        } else {
+            defined_in.insert(name, location.clone());
+        }
+    }
+    Ok(tasks
+        .into_iter()
+        .map(|(_, path, task)| (path, task))
+        .collect())
+}
+
+/// Tasks defined in the `tasks` of the up config, in the order they are
+/// defined (or sorted by name for `[tasks.<name>]` tables), with where in the
+/// up config each one is defined.
+fn inline_tasks(config: &config::UpConfig) -> Vec<(String, PathBuf, Result<task::Task>)> {
+    let (path, inline) = match (&config.up_toml_path, &config.config_toml.tasks) {
+        (Some(path), Some(inline)) => (path, inline),
+        _ => return Vec::new(),
+    };
+    let named: Vec<(String, Option<String>, task::TaskConfig)> = match inline {
+        config::InlineTasks::List(list) => list
+            .iter()
+            .enumerate()
+            .map(|(index, task_config)| {
+                let location = format!("'{}' [[tasks]] entry {}", path.display(), index + 1);
+                (location, task_config.name.clone(), task_config.clone())
+            })
+            .collect(),
+        config::InlineTasks::Named(named) => named
+            .iter()
+            .map(|(key, task_config)| {
+                let location = format!("'{}' [tasks.{}]", path.display(), key);
+                let name = task_config.name.clone().unwrap_or_else(|| key.clone());
+                (location, Some(name), task_config.clone())
+            })
+            .collect(),
+    };
+    named
+        .into_iter()
+        .enumerate()
+        .map(|(index, (location, name, task_config))| {
+            let task = name
+                .ok_or_else(|| {
+                    E::InlineTaskName {
+                        path: path.clone(),
+                        number: index + 1,
+                    }
+                    .into()
+                })
+                .and_then(|name| task::Task::new(name, path, task_config));
+            (location, path.clone(), task)
+        })
+        .collect()
+}
+
+/// Parse every task file in `tasks_dir`, sorted by path. A file that fails to
+/// parse doesn't stop the others from being read.
+pub(crate) fn read_tasks(tasks_dir: &Path) -> Result<Vec<(PathBuf, Result<task::Task>)>> {
+    let mut paths = Vec::new();
     for entry in tasks_dir.read_dir().map_err(|e| E::ReadDir {
-        path: tasks_dir.clone(),
+        path: tasks_dir.to_owned(),
         source: e,
     })? {
         let entry = entry?;
-        if entry.file_type()?.is_dir() {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() && !task::Task::is_task_dir(&path) {
+            debug!("Skipping directory that isn't a task: {}", path.display());
             continue;
         }
-        let path = entry.path();
         // If file is a broken symlink.
         if !path.exists() && path.symlink_metadata().is_ok() {
             warn!(
@@ -98,155 +313,902 @@ pub fn run(config: &config::UpConfig, tasks_dirname: &str) -> Result<()> {
             );
             continue;
         }
-        let task = task::Task::from(&path)?;
-        if let Some(filter) = filter_tasks_set.as_ref() {
-            if !filter.contains(&task.name) {
-                debug!(
-                    "Not running task '{}' as not in tasks filter {:?}",
-                    &task.name, &filter
-                );
+        paths.push(path);
+    }
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let task = task::Task::from(&path);
+            (path, task)
+        })
+        .collect())
+}
+
+/// How often to log which tasks are still running.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_mins(1);
+
+fn run_tasks(
+    bootstrap_tasks: Vec<String>,
+    tasks: HashMap<String, task::Task>,
+    env: &HashMap<String, String>,
+    config: &config::UpConfig,
+    tasks_dir: &Path,
+) -> Result<()> {
+    let mut scheduler = Scheduler::new(bootstrap_tasks, tasks, env, config, tasks_dir)
+        .map_err(|e| report_error(config, e))?;
+    let abort = task::Abort::default();
+    let (events_tx, events_rx) = mpsc::channel::<Event>();
+    let signal_tx = events_tx.clone();
+    let signals = task::catch_signals(move |signal| {
+        // Only fails if we've already stopped because of an error.
+        let _ = signal_tx.send(Event::Signal(signal));
+    });
+
+    let result = thread::scope(|scope| -> Result<()> {
+        while !scheduler.tasks_to_run.is_empty() {
+            // Whether anything changed in this pass, if not we wait for a task to finish.
+            let progressed = scheduler.start_tasks(scope, &abort, &events_tx)?;
+            scheduler.show_progress();
+            if progressed || scheduler.tasks_to_run.is_empty() {
                 continue;
             }
+            scheduler.wait_for_event(&events_rx, &abort)?;
         }
-        tasks.insert(task.name.clone(), task);
+        Ok(())
+    });
+    // Nothing is left to stop, so signals can act as they normally would.
+    drop(signals);
+    if let Err(e) = result {
+        return Err(scheduler.report_error(e, &events_rx));
     }
 
-    debug!("Task count: {:?}", tasks.len());
-    trace!("Task list: {:#?}", tasks);
+    scheduler.finish()
+}
 
-    run_tasks(bootstrap_tasks, tasks, &env)
+/// The state of a run while its tasks are scheduled, from validating what it
+/// will run to reporting the results.
+struct Scheduler<'a> {
+    config: &'a config::UpConfig,
+    env: &'a HashMap<String, String>,
+    max_parallel: Option<NonZeroUsize>,
+    /// Every task, apart from running ones which are taken out until they finish.
+    tasks: HashMap<String, task::Task>,
+    /// Bootstrap tasks still to run, the next one last.
+    bootstrap_tasks: Vec<String>,
+    /// Whether we're still running bootstrap tasks.
+    bootstrap: bool,
+    /// Tasks to run once any bootstrap tasks have finished.
+    post_bootstrap_tasks_to_run: Vec<String>,
+    /// Everything the run might start, including required tasks.
+    all_tasks_to_run: BTreeSet<String>,
+    /// Tasks being scheduled now, sorted so that runs with limited parallelism start
+    /// tasks in a consistent order.
+    tasks_to_run: BTreeSet<String>,
+    tasks_passed: Vec<String>,
+    tasks_skipped: Vec<String>,
+    tasks_failed: Vec<String>,
+    /// Tasks that failed, or that were skipped because something they required failed.
+    tasks_unsatisfied: HashSet<String>,
+    /// Tasks running on a worker thread, and when they started.
+    running: BTreeMap<String, Instant>,
+    /// When each task that was run started, to record in the state file.
+    started_at: HashMap<String, DateTime<Utc>>,
+    /// Dir the tasks were read from, which the state file is keyed by.
+    tasks_dir: &'a Path,
+    state_path: Option<PathBuf>,
+    state: State,
+    /// Tasks that need superuser privileges.
+    sudo_tasks: Vec<String>,
+    sudo_keepalive: Option<sudo::SudoKeepalive>,
+    progress: Option<TasksProgress>,
+    last_heartbeat: Instant,
+    /// Why the run was stopped early, if it was.
+    stopped_by: Option<Stopped>,
+    /// When to SIGKILL the commands of tasks that haven't exited since the run was stopped.
+    kill_at: Option<Instant>,
 }
 
-fn run_tasks(
-    mut bootstrap_tasks: Vec<String>,
-    mut tasks: HashMap<String, task::Task>,
-    env: &HashMap<String, String>,
-) -> Result<()> {
-    // TODO(gib): Allow vars to refer to other vars, detect cycles (topologically
-    // sort inputs).
-    let env_fn = &|s: &str| {
-        let out = shellexpand::full_with_context(s, dirs::home_dir, |k| {
-            env.get(k)
-                .ok_or_else(|| anyhow!("Value not found"))
-                .map(Some)
-        })
-        .map(std::borrow::Cow::into_owned)
-        .map_err(|e| TaskError::ResolveEnv {
-            var: e.var_name,
-            source: e.cause,
-        })?;
+impl<'a> Scheduler<'a> {
+    /// Work out what to run, and check it can be run, before starting anything.
+    fn new(
+        mut bootstrap_tasks: Vec<String>,
+        tasks: HashMap<String, task::Task>,
+        env: &'a HashMap<String, String>,
+        config: &'a config::UpConfig,
+        tasks_dir: &'a Path,
+    ) -> Result<Self> {
+        let filter_tasks_set: Option<HashSet<String>> =
+            config.tasks.clone().map(|v| v.into_iter().collect());
 
-        Ok(out)
-    };
+        let post_bootstrap_tasks_to_run: Vec<String> = tasks
+            .iter()
+            .filter(|(_, task)| task.config.auto_run.unwrap_or(true))
+            .filter(|(name, _)| match &filter_tasks_set {
+                None => true,
+                Some(filter) if filter.contains(*name) => true,
+                Some(filter) => {
+                    debug!(
+                        "Not running task '{}' as not in tasks filter {:?}",
+                        name, filter
+                    );
+                    false
+                }
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
 
-    #[allow(clippy::filter_map)]
-    let post_bootstrap_tasks_to_run: Vec<String> = tasks
-        .iter()
-        .filter(|(_, task)| task.config.auto_run.unwrap_or(true))
-        .map(|(name, _)| name.clone())
-        .collect();
-
-    let mut bootstrap = !bootstrap_tasks.is_empty();
-    let mut tasks_to_run: HashSet<String> = HashSet::new();
-    if let Some(task) = bootstrap_tasks.pop() {
-        tasks_to_run.insert(task);
-    } else {
-        tasks_to_run.extend(post_bootstrap_tasks_to_run.iter().cloned())
-    }
-
-    let mut tasks_passed = Vec::new();
-    let mut tasks_skipped = Vec::new();
-    let mut tasks_failed = Vec::new();
-    let mut task_errors: Vec<anyhow::Error> = Vec::new();
-
-    let mut tasks_to_run_completed = Vec::new();
-
-    while !tasks_to_run.is_empty() {
-        // TODO(gib): Remove or make tunable sleep delay.
-        // TODO(gib): Each minute log that we've been running for a minute, and how many
-        // of each task is still running.
-        thread::sleep(time::Duration::from_millis(10));
-        for name in &tasks_to_run {
-            let task = tasks
-                .get_mut(name)
-                .ok_or_else(|| anyhow!("Task '{}' was missing.", name))?;
+        // Validate the requires graph of everything we might run before starting anything.
+        let mut all_tasks_to_run = BTreeSet::new();
+        for name in bootstrap_tasks.iter().chain(&post_bootstrap_tasks_to_run) {
+            add_with_requires(name, &tasks, &mut all_tasks_to_run)?;
+        }
+        let cycles = requires_cycles(&all_tasks_to_run, |name| {
+            tasks
+                .get(name)
+                .and_then(|task| task.config.requires.as_deref())
+                .unwrap_or_default()
+        });
+        if !cycles.is_empty() {
+            bail!(E::RequiresCycle {
+                cycles: cycles
+                    .iter()
+                    .map(|cycle| cycle.join(" -> "))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            });
+        }
 
-            match task.status {
-                task::TaskStatus::New => {
-                    // Start the task or mark it as blocked.
-                    task.try_start(env_fn, env)?;
-                }
-                task::TaskStatus::Blocked => {
-                    // Check if still blocked, if not start it.
-                }
-                task::TaskStatus::Running(_, _) => {
-                    // Check if finished, if so gather status.
-                    task.try_finish()?;
-                }
-                task::TaskStatus::Failed(ref mut e) => {
-                    tasks_to_run_completed.push(name.clone());
-                    tasks_failed.push(name.clone());
-                    let extracted_error = std::mem::replace(e, anyhow!(""));
-                    task_errors.push(extracted_error);
+        let state_path = State::path();
+        let state = state_path
+            .as_deref()
+            .map(State::read_or_default)
+            .unwrap_or_default();
+
+        // Only ask for a password if something we're going to run needs it.
+        let sudo_tasks: Vec<String> = all_tasks_to_run
+            .iter()
+            .filter(|name| {
+                tasks.get(*name).is_some_and(|task| {
+                    task.needs_sudo(env)
+                        && passed_within_interval(task, &state, tasks_dir, config.force).is_none()
+                })
+            })
+            .cloned()
+            .collect();
+        let sudo_keepalive = if sudo_tasks.is_empty() || config.dry_run {
+            None
+        } else {
+            info!("Tasks {:?} need superuser privileges.", sudo_tasks);
+            Some(sudo::start(
+                config
+                    .config_toml
+                    .sudo_refresh_interval
+                    .map_or(sudo::DEFAULT_REFRESH_INTERVAL, Duration::from_secs),
+            )?)
+        };
+        let progress = TasksProgress::new(&all_tasks_to_run);
+
+        let bootstrap = !bootstrap_tasks.is_empty();
+        let mut tasks_to_run = BTreeSet::new();
+        if let Some(task) = bootstrap_tasks.pop() {
+            add_with_requires(&task, &tasks, &mut tasks_to_run)?;
+        } else {
+            for name in &post_bootstrap_tasks_to_run {
+                add_with_requires(name, &tasks, &mut tasks_to_run)?;
+            }
+        }
+
+        Ok(Self {
+            config,
+            env,
+            max_parallel: config.jobs.or(config.config_toml.max_parallel),
+            tasks,
+            bootstrap_tasks,
+            bootstrap,
+            post_bootstrap_tasks_to_run,
+            all_tasks_to_run,
+            tasks_to_run,
+            tasks_passed: Vec::new(),
+            tasks_skipped: Vec::new(),
+            tasks_failed: Vec::new(),
+            tasks_unsatisfied: HashSet::new(),
+            running: BTreeMap::new(),
+            started_at: HashMap::new(),
+            tasks_dir,
+            state_path,
+            state,
+            sudo_tasks,
+            sudo_keepalive,
+            progress,
+            last_heartbeat: Instant::now(),
+            stopped_by: None,
+            kill_at: None,
+        })
+    }
+
+    /// Start every task that is ready to run, record the ones that have finished,
+    /// and move on to the next set of tasks once these are done. Returns whether
+    /// anything changed.
+    fn start_tasks<'scope>(
+        &mut self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        abort: &'scope task::Abort,
+        events_tx: &mpsc::Sender<Event>,
+    ) -> Result<bool>
+    where
+        'a: 'scope,
+    {
+        let mut progressed = false;
+        let unfinished_count = self
+            .tasks_to_run
+            .iter()
+            .filter(|name| {
+                !matches!(
+                    self.tasks.get(*name).map(|t| &t.status),
+                    Some(
+                        task::TaskStatus::Passed
+                            | task::TaskStatus::Skipped
+                            | task::TaskStatus::Failed(_)
+                    )
+                )
+            })
+            .count();
+        // If this is the last task, let it use the terminal directly (the live display
+        // is paused while it runs so it doesn't draw over its output).
+        let passthrough = unfinished_count == 1;
+        let mut tasks_to_run_completed = Vec::new();
+        for name in &self.tasks_to_run.clone() {
+            // Running tasks aren't in `tasks`.
+            if self.running.contains_key(name) {
+                continue;
+            }
+            let task = self
+                .tasks
+                .get(name)
+                .ok_or_else(|| anyhow!("Task '{}' was missing.", name))?;
+            let finished_list = match task.status {
+                task::TaskStatus::New | task::TaskStatus::Blocked | task::TaskStatus::Queued => {
+                    // If the run was stopped, don't start anything else.
+                    if self.stopped_by.is_none() {
+                        progressed |= self.try_start(name, passthrough, scope, abort, events_tx)?;
+                    }
+                    continue;
                 }
-                task::TaskStatus::Passed => {
-                    tasks_to_run_completed.push(name.clone());
-                    tasks_passed.push(name.clone());
+                task::TaskStatus::Running => continue,
+                task::TaskStatus::Failed(_) => {
+                    self.tasks_unsatisfied.insert(name.clone());
+                    &mut self.tasks_failed
                 }
-                task::TaskStatus::Skipped => {
-                    tasks_to_run_completed.push(name.clone());
-                    tasks_skipped.push(name.clone());
+                task::TaskStatus::Passed => &mut self.tasks_passed,
+                task::TaskStatus::Skipped => &mut self.tasks_skipped,
+            };
+            finished_list.push(name.clone());
+            tasks_to_run_completed.push(name.clone());
+        }
+        progressed |= !tasks_to_run_completed.is_empty();
+        for name in tasks_to_run_completed {
+            self.tasks_to_run.remove(&name);
+        }
+        self.next_tasks()?;
+        Ok(progressed)
+    }
+
+    /// Start task `name` on a worker thread if everything it requires has
+    /// finished, or skip it if it doesn't need to run. Returns whether anything
+    /// changed.
+    fn try_start<'scope>(
+        &mut self,
+        name: &str,
+        passthrough: bool,
+        scope: &'scope thread::Scope<'scope, '_>,
+        abort: &'scope task::Abort,
+        events_tx: &mpsc::Sender<Event>,
+    ) -> Result<bool>
+    where
+        'a: 'scope,
+    {
+        let task = self
+            .tasks
+            .get(name)
+            .ok_or_else(|| anyhow!("Task '{}' was missing.", name))?;
+        let (status, progressed) = match requires_state(task, &self.tasks, &self.tasks_unsatisfied)
+        {
+            RequiresState::Satisfied => {
+                // How long ago the task passed, if it was within its run interval.
+                let ran_ago =
+                    passed_within_interval(task, &self.state, self.tasks_dir, self.config.force);
+                let sudo_expired = self
+                    .sudo_keepalive
+                    .as_ref()
+                    .filter(|_| self.sudo_tasks.iter().any(|sudo_task| sudo_task == name))
+                    .and_then(sudo::SudoKeepalive::expired);
+                if let Some(ago) = ran_ago {
+                    info!(
+                        "Skipping task '{}' as it ran {} ago.",
+                        name,
+                        state::format_ago(ago)
+                    );
+                    (task::TaskStatus::Skipped, true)
+                } else if matches!(self.max_parallel, Some(max) if self.running.len() >= max.get())
+                {
+                    // Wait for a free job slot.
+                    (task::TaskStatus::Queued, false)
+                } else if let Some(error) = sudo_expired {
+                    // It would hang on a password prompt.
+                    let error = sudo::SudoError::Expired {
+                        name: name.to_owned(),
+                        error,
+                    };
+                    (task::TaskStatus::Failed(error.into()), true)
+                } else {
+                    let task = self
+                        .tasks
+                        .remove(name)
+                        .ok_or_else(|| anyhow!("Task '{}' was missing.", name))?;
+                    self.start(task, passthrough, scope, abort, events_tx);
+                    return Ok(true);
                 }
             }
+            // Still waiting for required tasks to finish.
+            RequiresState::Pending => (task::TaskStatus::Blocked, false),
+            RequiresState::Unsatisfied(required) => {
+                warn!(
+                    "Skipping task '{}' as required task '{}' failed.",
+                    name, required
+                );
+                self.tasks_unsatisfied.insert(name.to_owned());
+                (task::TaskStatus::Skipped, true)
+            }
+        };
+        if let Some(task) = self.tasks.get_mut(name) {
+            task.status = status;
         }
-        for name in tasks_to_run_completed.drain(..) {
-            tasks_to_run.remove(&name);
+        Ok(progressed)
+    }
+
+    /// Run `task` on a worker thread, sending it back on `events_tx` when it
+    /// finishes.
+    fn start<'scope>(
+        &mut self,
+        mut task: task::Task,
+        passthrough: bool,
+        scope: &'scope thread::Scope<'scope, '_>,
+        abort: &'scope task::Abort,
+        events_tx: &mpsc::Sender<Event>,
+    ) where
+        'a: 'scope,
+    {
+        if passthrough {
+            if let Some(progress) = &mut self.progress {
+                progress.pause();
+            }
         }
-        if tasks_to_run.is_empty() {
-            if let Some(task) = bootstrap_tasks.pop() {
-                tasks_to_run.insert(task);
-            } else if bootstrap {
-                bootstrap = false;
-                tasks_to_run.extend(post_bootstrap_tasks_to_run.iter().cloned())
+        self.running.insert(task.name.clone(), Instant::now());
+        self.started_at.insert(task.name.clone(), Utc::now());
+        let events_tx = events_tx.clone();
+        let (env, config) = (self.env, self.config);
+        scope.spawn(move || {
+            // A panicking run lib mustn't leave the scheduler waiting for the task forever.
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| {
+                task.run(env, config, passthrough, abort);
+            })) {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| (*s).to_owned())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                task.status = task::TaskStatus::Failed(
+                    E::TaskPanicked {
+                        name: task.name.clone(),
+                        message,
+                    }
+                    .into(),
+                );
+            }
+            // Only fails if we've already stopped because of an error.
+            let _ = events_tx.send(Event::Finished(Box::new(task)));
+        });
+    }
+
+    /// Once the current tasks are done move on from the bootstrap tasks, or if
+    /// the run was stopped only wait for the tasks that are still running.
+    fn next_tasks(&mut self) -> Result<()> {
+        if self.stopped_by.is_some() {
+            let running = &self.running;
+            self.tasks_to_run.retain(|name| running.contains_key(name));
+        } else if self.tasks_to_run.is_empty() {
+            if let Some(task) = self.bootstrap_tasks.pop() {
+                add_with_requires(&task, &self.tasks, &mut self.tasks_to_run)?;
+            } else if self.bootstrap {
+                self.bootstrap = false;
+                for name in &self.post_bootstrap_tasks_to_run {
+                    add_with_requires(name, &self.tasks, &mut self.tasks_to_run)?;
+                }
             } else {
                 // We're done.
             }
         }
+        Ok(())
     }
 
-    info!(
-        "Ran {} tasks, {} passed, {} failed, {} skipped",
-        tasks.len(),
-        tasks_passed.len(),
-        tasks_failed.len(),
-        tasks_skipped.len()
-    );
-    if !tasks_passed.is_empty() {
-        info!("Tasks passed: {:?}", tasks_passed);
+    /// Update the live display with the state of each task, if it is shown.
+    fn show_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            for name in &self.all_tasks_to_run {
+                let state = match self.tasks.get(name) {
+                    Some(task) if !self.running.contains_key(name) => BarState::from(&task.status),
+                    _ => BarState::Running,
+                };
+                progress.set_state(name, state);
+            }
+        }
     }
-    if !tasks_skipped.is_empty() {
-        info!("Tasks skipped: {:?}", tasks_skipped);
+
+    /// Wait for a task to finish or a signal, logging what's still running every
+    /// so often, and killing tasks that haven't exited in time after the run was
+    /// stopped.
+    fn wait_for_event(
+        &mut self,
+        events_rx: &mpsc::Receiver<Event>,
+        abort: &task::Abort,
+    ) -> Result<()> {
+        if self.running.is_empty() {
+            bail!(
+                "Tasks {:?} are waiting, but no tasks are running.",
+                self.tasks_to_run
+            );
+        }
+
+        let mut wait_time = HEARTBEAT_INTERVAL.saturating_sub(self.last_heartbeat.elapsed());
+        if let Some(kill_at) = self.kill_at {
+            wait_time = wait_time.min(kill_at.saturating_duration_since(Instant::now()));
+        }
+        match events_rx.recv_timeout(wait_time) {
+            Ok(Event::Finished(task)) => self.finished(*task, abort),
+            Ok(Event::Signal(signal)) => self.interrupted(signal, abort),
+            Err(RecvTimeoutError::Timeout) => {
+                if self
+                    .kill_at
+                    .is_some_and(|kill_at| kill_at <= Instant::now())
+                {
+                    self.kill_at = None;
+                    warn!(
+                        "Killing tasks still running after {:?}: {:?}",
+                        TERMINATE_GRACE_PERIOD,
+                        self.running.keys().collect::<Vec<_>>()
+                    );
+                    abort.abort(libc::SIGKILL);
+                }
+                if self.last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                    self.last_heartbeat = Instant::now();
+                    let still_running: Vec<String> = self
+                        .running
+                        .iter()
+                        .map(|(name, start)| {
+                            format!(
+                                "{} ({:?})",
+                                name,
+                                Duration::from_secs(start.elapsed().as_secs())
+                            )
+                        })
+                        .collect();
+                    info!("Still running: {}", still_running.join(", "));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("Task worker threads stopped unexpectedly.")
+            }
+        }
+        Ok(())
     }
 
-    if !tasks_failed.is_empty() {
-        // Error out.
-        error!("Tasks failed: {:#?}", tasks_failed);
-        error!("One or more tasks failed, exiting.");
-        return Err(anyhow!("")).with_context(|| {
-            let task_errors_string = task_errors
-                .into_iter()
-                .fold(String::new(), |acc, e| acc + &format!("\n- {:?}", e));
-            anyhow!("Task errors: {}", task_errors_string)
+    /// Put a task back once its worker thread has finished running it, stopping
+    /// the run if its failure should.
+    fn finished(&mut self, task: task::Task, abort: &task::Abort) {
+        self.running.remove(&task.name);
+        // Only a task running on its own pauses the display.
+        if let Some(progress) = &mut self.progress {
+            progress.resume();
+        }
+        if self.stopped_by.is_none()
+            && matches!(task.status, task::TaskStatus::Failed(_))
+            && stops_run(self.config.on_failure, &task)
+        {
+            error!(
+                "Task '{}' failed, stopping the run and terminating running tasks.",
+                task.name
+            );
+            abort.abort(libc::SIGTERM);
+            self.kill_at = Some(Instant::now() + TERMINATE_GRACE_PERIOD);
+            self.stopped_by = Some(Stopped::TaskFailed(task.name.clone()));
+        }
+        self.tasks.insert(task.name.clone(), task);
+    }
+
+    /// Stop the run after we were sent `signal`, terminating the running
+    /// commands as if a task had failed. If it happens again kill them rather
+    /// than waiting.
+    fn interrupted(&mut self, signal: libc::c_int, abort: &task::Abort) {
+        if matches!(self.stopped_by, Some(Stopped::Signal(_))) {
+            warn!(
+                "Interrupted again, killing running tasks: {:?}",
+                self.running.keys().collect::<Vec<_>>()
+            );
+            abort.abort(libc::SIGKILL);
+            self.kill_at = None;
+            return;
+        }
+        error!(
+            "Interrupted by {}, stopping the run and terminating running tasks.",
+            signal_name(signal)
+        );
+        // SIGTERM as background commands started by a script ignore SIGINT.
+        abort.abort(libc::SIGTERM);
+        self.kill_at = Some(Instant::now() + TERMINATE_GRACE_PERIOD);
+        self.stopped_by = Some(Stopped::Signal(signal));
+    }
+
+    /// Save the state file, log and report the results, and fail if any task
+    /// failed.
+    fn finish(mut self) -> Result<()> {
+        drop(self.progress.take());
+        let dry_run = self.config.dry_run;
+        if !dry_run {
+            self.save_state();
+        }
+
+        if let Some(error) = self
+            .sudo_keepalive
+            .take()
+            .and_then(sudo::SudoKeepalive::stop)
+        {
+            error!(
+                "Superuser privileges expired during the run, tasks that used sudo may have \
+                 failed or hung: {}",
+                error
+            );
+        }
+        let tasks_not_started = self.tasks_not_started();
+        self.log_summary();
+
+        match &self.stopped_by {
+            Some(Stopped::TaskFailed(failed)) => warn!(
+                "Run stopped as task '{}' failed, tasks not started: {:?}",
+                failed, tasks_not_started
+            ),
+            Some(Stopped::Signal(signal)) => warn!(
+                "Run stopped by {}, tasks not started: {:?}",
+                signal_name(*signal),
+                tasks_not_started
+            ),
+            None => {}
+        }
+
+        if let Some(report_path) = &self.config.report {
+            report::write(report_path, &self.tasks, &tasks_not_started, dry_run, None)?;
+        }
+
+        if !self.tasks_failed.is_empty() {
+            // Error out.
+            error!("Tasks failed: {:#?}", self.tasks_failed);
+            error!("One or more tasks failed, exiting.");
+            return Err(anyhow!("")).with_context(|| {
+                let task_errors_string = self
+                    .tasks_failed
+                    .iter()
+                    .filter_map(|name| match self.tasks.get(name).map(|t| &t.status) {
+                        Some(task::TaskStatus::Failed(e)) => Some(e),
+                        _ => None,
+                    })
+                    .fold(String::new(), |acc, e| acc + &format!("\n- {:?}", e));
+                anyhow!("Task errors: {}", task_errors_string)
+            });
+        }
+        if let Some(Stopped::Signal(signal)) = self.stopped_by {
+            bail!(E::Interrupted {
+                signal: signal_name(signal),
+            });
+        }
+        Ok(())
+    }
+
+    /// Record when each task that was started ran, and how it went, keeping
+    /// anything other runs have recorded since this one started.
+    fn save_state(&mut self) {
+        let state_path = match &self.state_path {
+            Some(state_path) => state_path,
+            None => return,
+        };
+        let (tasks, tasks_dir, started_at) = (&self.tasks, self.tasks_dir, &mut self.started_at);
+        let result = State::update(state_path, |state| {
+            for (name, last_run) in started_at.drain() {
+                if let Some(task) = tasks.get(&name) {
+                    let duration = task.check_duration.unwrap_or_default()
+                        + task.run_duration.unwrap_or_default();
+                    state.record(
+                        tasks_dir,
+                        name,
+                        last_run,
+                        report::status_name(&task.status),
+                        duration,
+                    );
+                }
+            }
         });
+        if let Err(e) = result {
+            warn!("Failed to save task state: {:#}", e);
+        }
+    }
+
+    /// Tasks that would have run if the run hadn't been stopped.
+    fn tasks_not_started(&self) -> Vec<String> {
+        if self.stopped_by.is_none() {
+            return Vec::new();
+        }
+        self.unstarted_tasks()
+    }
+
+    /// Tasks the run might have started that haven't been.
+    fn unstarted_tasks(&self) -> Vec<String> {
+        self.all_tasks_to_run
+            .iter()
+            .filter(|name| {
+                matches!(
+                    self.tasks.get(*name).map(|t| &t.status),
+                    Some(
+                        task::TaskStatus::New
+                            | task::TaskStatus::Blocked
+                            | task::TaskStatus::Queued
+                    )
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Write the `--report` (if requested) for a run that failed with `error` while its tasks were
+    /// being scheduled, including the tasks that finished and those that weren't started, and
+    /// return the error.
+    fn report_error(
+        mut self,
+        error: anyhow::Error,
+        events_rx: &mpsc::Receiver<Event>,
+    ) -> anyhow::Error {
+        drop(self.progress.take());
+        let config = self.config;
+        if let Some(report_path) = &config.report {
+            // The worker threads have all exited, so collect the tasks that finished since the
+            // error.
+            while let Ok(event) = events_rx.try_recv() {
+                if let Event::Finished(task) = event {
+                    self.tasks.insert(task.name.clone(), *task);
+                }
+            }
+            let tasks_not_started = self.unstarted_tasks();
+            if let Err(e) = report::write(
+                report_path,
+                &self.tasks,
+                &tasks_not_started,
+                config.dry_run,
+                Some(&error),
+            ) {
+                warn!("{:?}", e);
+            }
+        }
+        error
+    }
+
+    /// Log how many tasks passed, failed, and were skipped.
+    fn log_summary(&self) {
+        let dry_run = self.config.dry_run;
+        // Tasks that used sudo, i.e. weren't skipped by their check command.
+        let tasks_escalated: Vec<&String> = self
+            .sudo_tasks
+            .iter()
+            .filter(|name| {
+                self.tasks.get(*name).is_some_and(|task| {
+                    if dry_run {
+                        matches!(task.status, task::TaskStatus::Passed)
+                    } else {
+                        task.attempts > 0
+                    }
+                })
+            })
+            .collect();
+
+        if dry_run {
+            info!(
+                "Checked {} tasks, {} would run, {} failed, {} would be skipped",
+                self.tasks_passed.len() + self.tasks_failed.len() + self.tasks_skipped.len(),
+                self.tasks_passed.len(),
+                self.tasks_failed.len(),
+                self.tasks_skipped.len()
+            );
+            if !self.tasks_passed.is_empty() {
+                info!("Tasks that would run: {:?}", self.tasks_passed);
+            }
+            if !self.tasks_skipped.is_empty() {
+                info!("Tasks that would be skipped: {:?}", self.tasks_skipped);
+            }
+            if !tasks_escalated.is_empty() {
+                info!("Tasks that would run with sudo: {:?}", tasks_escalated);
+            }
+        } else {
+            info!(
+                "Ran {} tasks, {} passed, {} failed, {} skipped",
+                self.tasks_passed.len() + self.tasks_failed.len() + self.tasks_skipped.len(),
+                self.tasks_passed.len(),
+                self.tasks_failed.len(),
+                self.tasks_skipped.len()
+            );
+            if !self.tasks_passed.is_empty() {
+                info!("Tasks passed: {:?}", self.tasks_passed);
+            }
+            if !self.tasks_skipped.is_empty() {
+                info!("Tasks skipped: {:?}", self.tasks_skipped);
+            }
+            if !tasks_escalated.is_empty() {
+                info!("Tasks run with sudo: {:?}", tasks_escalated);
+            }
+        }
+    }
+}
+
+/// How long ago `task` last passed, if it was within its `run_interval` so it
+/// should be skipped. Tasks always run with `--force`.
+fn passed_within_interval(
+    task: &task::Task,
+    state: &State,
+    tasks_dir: &Path,
+    force: bool,
+) -> Option<Duration> {
+    task.config
+        .run_interval
+        .filter(|_| !force)
+        .and_then(|interval| {
+            state.passed_within(tasks_dir, &task.name, Duration::from_secs(interval))
+        })
+}
+
+/// What the scheduler waits for while tasks are running.
+enum Event {
+    /// A worker thread has finished running this task.
+    Finished(Box<task::Task>),
+    /// We were sent SIGINT or SIGTERM.
+    Signal(libc::c_int),
+}
+
+/// Why a run was stopped before all its tasks had run.
+#[derive(Debug)]
+enum Stopped {
+    /// A task failed, and its failure should stop the run.
+    TaskFailed(String),
+    /// We were sent SIGINT or SIGTERM.
+    Signal(libc::c_int),
+}
+
+/// Name of a signal we catch, for messages.
+fn signal_name(signal: libc::c_int) -> String {
+    match signal {
+        libc::SIGINT => "SIGINT".to_owned(),
+        libc::SIGTERM => "SIGTERM".to_owned(),
+        _ => format!("signal {}", signal),
+    }
+}
+
+/// Whether a failure of `task` should stop the run.
+fn stops_run(on_failure: OnFailure, task: &task::Task) -> bool {
+    match on_failure {
+        OnFailure::Stop => true,
+        OnFailure::KeepGoing => false,
+        OnFailure::StopIfCritical => task.config.critical.unwrap_or(false),
+    }
+}
+
+/// Whether the tasks a task requires have finished.
+enum RequiresState {
+    /// Everything required has passed or been skipped.
+    Satisfied,
+    /// Some required tasks haven't finished yet.
+    Pending,
+    /// A required task failed, or was itself skipped because of a failure.
+    Unsatisfied(String),
+}
+
+fn requires_state(
+    task: &task::Task,
+    tasks: &HashMap<String, task::Task>,
+    tasks_unsatisfied: &HashSet<String>,
+) -> RequiresState {
+    let mut state = RequiresState::Satisfied;
+    for required in task.config.requires.iter().flatten() {
+        if tasks_unsatisfied.contains(required) {
+            return RequiresState::Unsatisfied(required.clone());
+        }
+        match tasks.get(required).map(|t| &t.status) {
+            Some(task::TaskStatus::Passed | task::TaskStatus::Skipped) => {}
+            Some(task::TaskStatus::Failed(_)) => {
+                return RequiresState::Unsatisfied(required.clone());
+            }
+            _ => state = RequiresState::Pending,
+        }
+    }
+    state
+}
+
+/// Add `name` and everything it (transitively) requires to `tasks_to_run`.
+/// Tasks that have already been started are not added again.
+fn add_with_requires(
+    name: &str,
+    tasks: &HashMap<String, task::Task>,
+    tasks_to_run: &mut BTreeSet<String>,
+) -> Result<()> {
+    let task = tasks.get(name).ok_or_else(|| E::MissingTask {
+        name: name.to_owned(),
+    })?;
+    if !matches!(task.status, task::TaskStatus::New) || !tasks_to_run.insert(name.to_owned()) {
+        return Ok(());
+    }
+    for required in task.config.requires.iter().flatten() {
+        if !tasks.contains_key(required) {
+            bail!(E::UnknownRequires {
+                name: name.to_owned(),
+                required: required.clone(),
+            });
+        }
+        add_with_requires(required, tasks, tasks_to_run)?;
     }
     Ok(())
 }
 
+/// Every cycle in the requires graph reachable from `names`, each as the task
+/// names that form it with the first task repeated at the end. `requires`
+/// returns the tasks a task requires. Used by both `up run` and `up graph`.
+pub(crate) fn requires_cycles<'a, I, F>(names: I, requires: F) -> Vec<Vec<String>>
+where
+    I: IntoIterator<Item = &'a String>,
+    F: Fn(&str) -> &'a [String],
+{
+    fn visit<'a>(
+        name: &str,
+        requires: &dyn Fn(&str) -> &'a [String],
+        finished: &mut HashSet<String>,
+        path: &mut Vec<String>,
+        cycles: &mut Vec<Vec<String>>,
+    ) {
+        if let Some(index) = path.iter().position(|n| n == name) {
+            let mut cycle = path[index..].to_vec();
+            cycle.push(name.to_owned());
+            cycles.push(cycle);
+            return;
+        }
+        if finished.contains(name) {
+            return;
+        }
+        path.push(name.to_owned());
+        for required in requires(name) {
+            visit(required, requires, finished, path, cycles);
+        }
+        path.pop();
+        finished.insert(name.to_owned());
+    }
+
+    let mut cycles = Vec::new();
+    let mut finished = HashSet::new();
+    for name in names {
+        visit(name, &requires, &mut finished, &mut Vec::new(), &mut cycles);
+    }
+    cycles
+}
+
 #[derive(Error, Debug, Display)]
 /// Errors thrown by this file.
 pub enum TasksError {
     /// Error walking directory '{path}':
     ReadDir { path: PathBuf, source: io::Error },
+    /// Error creating directory '{path}':
+    CreateDir { path: PathBuf, source: io::Error },
     /// Error reading file '{path}':
     ReadFile { path: PathBuf, source: io::Error },
     /// Env lookup error, please define '{var}' in your up.toml:"
@@ -259,11 +1221,151 @@ pub enum TasksError {
         source: io::Error,
         cmd: Vec<String>,
     },
+    /// Task '{name}' failed to start {command} {cmd:?} in '{cwd}'.
+    CmdSpawn {
+        name: String,
+        command: CommandType,
+        cmd: Vec<String>,
+        cwd: PathBuf,
+        source: io::Error,
+    },
+    /// Task '{name}' {command} failed with {status}.{details}
+    CmdFailed {
+        name: String,
+        command: CommandType,
+        status: ExitStatus,
+        details: String,
+    },
+    /// Task '{name}' {command} timed out after {timeout:?}.{details}
+    CmdTimedOut {
+        name: String,
+        command: CommandType,
+        timeout: Duration,
+        details: String,
+    },
+    /// Task '{name}' failed to start plugin '{plugin}'.
+    PluginSpawn {
+        name: String,
+        plugin: PathBuf,
+        source: io::Error,
+    },
+    /// Task '{name}' plugin '{plugin}' failed in {mode} mode with {status}.{details}
+    PluginFailed {
+        name: String,
+        plugin: PathBuf,
+        mode: plugin::Mode,
+        status: ExitStatus,
+        details: String,
+    },
+    /// Task '{name}' panicked: {message}
+    TaskPanicked { name: String, message: String },
+    /// Task '{name}' {command} was terminated as the run was stopped.{details}
+    CmdAborted {
+        name: String,
+        command: CommandType,
+        details: String,
+    },
+    /// Run interrupted by {signal}.
+    Interrupted { signal: String },
+    /// Task '{name}' working directory '{path}' doesn't exist.
+    MissingCwd { name: String, path: PathBuf },
     /// Unexpectedly empty option found.
     None {},
+    /// Task '{name}' was not found in the tasks directory or up.toml.
+    MissingTask { name: String },
+    /// Task '{name}' requires task '{required}', not found in the tasks directory or up.toml.
+    UnknownRequires { name: String, required: String },
+    /// Task requires form cycles: {cycles}.
+    RequiresCycle { cycles: String },
     /// Invalid toml at '{path}':
     InvalidToml {
         path: PathBuf,
         source: toml::de::Error,
     },
+    /// Task '{name}' is defined twice, in {first} and in {second}.
+    DuplicateTask {
+        name: String,
+        first: String,
+        second: String,
+    },
+    /// Inline task number {number} in '{path}' has no name, `[[tasks]]` entries must set one.
+    InlineTaskName { path: PathBuf, number: usize },
+    /// Task directory '{path}' has both a '{script}' script and a {field} in its config.
+    DirCmdConflict {
+        path: PathBuf,
+        script: &'static str,
+        field: &'static str,
+    },
+    /// Invalid yaml at '{path}':
+    InvalidYaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    /// Invalid json at '{path}':
+    InvalidJson {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tasks_tests {
+    use std::{
+        collections::HashMap, env, os::unix::process::ExitStatusExt, path::Path, process::Command,
+        thread, time::Duration,
+    };
+
+    use crate::{
+        config::UpConfig,
+        tasks::task::{Task, TaskConfig},
+    };
+
+    /// Set in the test process [`signal_after_run`] starts.
+    const SIGNAL_AFTER_RUN_CHILD: &str = "UP_TEST_SIGNAL_AFTER_RUN_CHILD";
+
+    /// Once a run has finished, a signal should no longer be caught, so it
+    /// still terminates the process (e.g. for programs embedding `up_rs`).
+    #[test]
+    fn signal_after_run() {
+        if env::var_os(SIGNAL_AFTER_RUN_CHILD).is_some() {
+            let config = TaskConfig {
+                run_cmd: Some(vec!["true".to_owned()]),
+                ..TaskConfig::default()
+            };
+            let mut tasks = HashMap::new();
+            tasks.insert(
+                "true".to_owned(),
+                Task::new("true".to_owned(), &env::temp_dir().join("up.toml"), config).unwrap(),
+            );
+            // Dry run, so the run isn't recorded in the state file.
+            let config = UpConfig {
+                dry_run: true,
+                ..UpConfig::default()
+            };
+            super::run_tasks(
+                Vec::new(),
+                tasks,
+                &HashMap::new(),
+                &config,
+                Path::new("tasks"),
+            )
+            .unwrap();
+            // Safety: raise has no memory safety requirements.
+            unsafe {
+                libc::raise(libc::SIGTERM);
+            }
+            // Exits successfully if the signal was swallowed.
+            thread::sleep(Duration::from_secs(5));
+            return;
+        }
+
+        // Run just this test again in a child process, as it should be terminated.
+        let status = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "tasks::tasks_tests::signal_after_run"])
+            .env(SIGNAL_AFTER_RUN_CHILD, "1")
+            .status()
+            .unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM), "{:?}", status);
+    }
 }
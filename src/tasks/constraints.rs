@@ -0,0 +1,127 @@
+//! Constraints restrict tasks to the machines they should run on.
+//!
+//! Constraints are set in a task's `constraints` table, and every constraint must match for the
+//! task to run. Prefix a value with `!` to require that it doesn't match.
+//!
+//! | Key          | Matches                                        |
+//! | ---          | ---                                            |
+//! | `os`         | Operating system, e.g. `linux` or `macos`.     |
+//! | `arch`       | CPU architecture, e.g. `x86_64` or `aarch64`.  |
+//! | `hostname`   | Machine hostname (as returned by `uname -n`).  |
+//! | `env.<VAR>`  | Value of the `<VAR>` environment variable.     |
+//! | `executable` | Whether the executable can be found on `PATH`. |
+
+use std::{
+    collections::HashMap,
+    env, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Result;
+use displaydoc::Display;
+use log::trace;
+use thiserror::Error;
+
+use self::ConstraintsError as E;
+
+/// Check that every key in task `name`'s `constraints` is one we know how to match, so a typo is
+/// an error when the task is loaded rather than when it is run.
+pub(crate) fn validate(name: &str, constraints: &HashMap<String, String>) -> Result<()> {
+    let mut keys: Vec<&String> = constraints.keys().collect();
+    keys.sort();
+    for key in keys {
+        if !is_known(key) {
+            return Err(E::UnknownConstraint {
+                name: name.to_owned(),
+                key: key.clone(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `key` is a constraint we can match.
+fn is_known(key: &str) -> bool {
+    matches!(key, "os" | "arch" | "hostname" | "executable") || key.starts_with("env.")
+}
+
+/// Check task `name`'s `constraints` against the current machine. Returns a description of the
+/// first constraint that didn't match, or `None` if they all matched.
+pub(crate) fn failed_constraint<F>(
+    name: &str,
+    constraints: &HashMap<String, String>,
+    env_fn: F,
+    env: &HashMap<String, String>,
+) -> Result<Option<String>>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    validate(name, constraints)?;
+    let mut keys: Vec<&String> = constraints.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = env_fn(&constraints[key])?;
+        let (negated, expected) = value
+            .strip_prefix('!')
+            .map_or((false, value.as_str()), |v| (true, v));
+
+        let actual = match key.as_str() {
+            "os" => Some(env::consts::OS.to_owned()),
+            "arch" => Some(env::consts::ARCH.to_owned()),
+            "hostname" => Some(hostname()?),
+            "executable" => find_executable(expected, env).map(|_| expected.to_owned()),
+            k => k
+                .strip_prefix("env.")
+                .and_then(|var| env.get(var).cloned().or_else(|| env::var(var).ok())),
+        };
+        trace!(
+            "Constraint {} = {:?}, actual value: {:?}",
+            key,
+            value,
+            actual
+        );
+
+        if (actual.as_deref() == Some(expected)) == negated {
+            let found = actual.map_or_else(|| "nothing".to_owned(), |a| format!("{:?}", a));
+            return Ok(Some(format!("{} = {:?} (found {})", key, value, found)));
+        }
+    }
+    Ok(None)
+}
+
+/// Get the hostname of the current machine.
+fn hostname() -> Result<String> {
+    let output = Command::new("uname")
+        .arg("-n")
+        .output()
+        .map_err(|e| E::Hostname { source: e })?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Find `name` on the task `PATH` (falling back to the `PATH` of the current process).
+pub(crate) fn find_executable(name: &str, env: &HashMap<String, String>) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name)).filter(|p| is_executable(p));
+    }
+    let path = env.get("PATH").cloned().or_else(|| env::var("PATH").ok())?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|p| is_executable(p))
+}
+
+/// Whether `path` is a file that can be executed.
+pub(crate) fn is_executable(path: &Path) -> bool {
+    matches!(path.metadata(), Ok(m) if m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum ConstraintsError {
+    /// Task '{name}' constraint '{key}' isn't one of os, arch, hostname, executable, env.<VAR>.
+    UnknownConstraint { name: String, key: String },
+    /// Failed to get the hostname by running 'uname -n'.
+    Hostname { source: io::Error },
+}
@@ -27,7 +27,7 @@ use crate::tasks::{defaults::DefaultsError as E, ResolveEnv};
 
 impl ResolveEnv for DefaultsConfig {}
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DefaultsConfig(HashMap<String, HashMap<String, toml::Value>>);
 
 // TODO(gib): Pass by reference instead.
@@ -24,7 +24,7 @@ pub mod update;
 
 pub const DEFAULT_REMOTE_NAME: &str = "origin";
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GitConfig {
     /// Path to download git repo to.
     pub path: String,
@@ -105,7 +105,7 @@ impl ResolveEnv for Vec<GitConfig> {
     }
 }
 
-#[derive(Debug, Default, StructOpt, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, StructOpt, Serialize, Deserialize)]
 pub struct GitRemote {
     /// Name of the remote to set in git.
     pub name: String,
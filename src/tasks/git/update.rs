@@ -1,6 +1,10 @@
-// TODO(gib): Use https://lib.rs/crates/indicatif for progress bars and remove this.
-#![allow(clippy::print_stdout, clippy::unwrap_used)]
-use std::{borrow::ToOwned, fs, path::PathBuf, str};
+#![allow(clippy::unwrap_used)]
+use std::{
+    borrow::ToOwned,
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
 
 use anyhow::{bail, ensure, Context, Result};
 use git2::{BranchType, ConfigLevel, ErrorCode, FetchOptions, Repository};
@@ -8,15 +12,18 @@ use itertools::Itertools;
 use log::{debug, trace};
 use url::Url;
 
-use crate::tasks::git::{
-    branch::{calculate_head, get_branch_name, get_push_branch, shorten_branch_ref},
-    checkout::{checkout_branch, needs_checkout},
-    errors::GitError as E,
-    fetch::{remote_callbacks, set_remote_head},
-    merge::do_merge,
-    prune::prune_merged_branches,
-    status::warn_for_unpushed_changes,
-    GitConfig, GitRemote,
+use crate::{
+    progress,
+    tasks::git::{
+        branch::{calculate_head, get_branch_name, get_push_branch, shorten_branch_ref},
+        checkout::{checkout_branch, needs_checkout},
+        errors::GitError as E,
+        fetch::{remote_callbacks, set_remote_head},
+        merge::do_merge,
+        prune::prune_merged_branches,
+        status::warn_for_unpushed_changes,
+        GitConfig, GitRemote,
+    },
 };
 
 pub(crate) fn update(git_config: &GitConfig) -> Result<()> {
@@ -63,13 +70,7 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<()> {
         debug!("Newly created repo, will force overwrite repo contents.");
     }
 
-    // Opens the global, XDG, and system files in order.
-    let mut user_git_config = git2::Config::open_default()?;
-    // Then add the local one if defined.
-    let local_git_config_path = git_path.join(".git/config");
-    if local_git_config_path.exists() {
-        user_git_config.add_file(&local_git_config_path, ConfigLevel::Local, false)?;
-    }
+    let user_git_config = open_git_config(&git_path)?;
 
     // Set up remotes.
     ensure!(!git_config.remotes.is_empty(), E::NoRemotes);
@@ -152,6 +153,77 @@ pub(crate) fn real_update(git_config: &GitConfig) -> Result<()> {
     Ok(())
 }
 
+/// Whether the repo already matches `git_config`, as far as we can tell without
+/// fetching: it exists with the remotes set up, it's on the branch, and the
+/// branch already contains what it would be updated to from the last fetch.
+/// Repos that would be pruned are never up to date.
+pub(crate) fn is_up_to_date(git_config: &GitConfig) -> Result<bool> {
+    let git_path = PathBuf::from(&git_config.path);
+    let repo = match Repository::open(&git_path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            debug!("Failed to open repo '{}': {}", git_path.display(), e);
+            return Ok(false);
+        }
+    };
+    if git_config.remotes.is_empty() || git_config.prune {
+        return Ok(false);
+    }
+    for remote_config in &git_config.remotes {
+        let remote = match repo.find_remote(&remote_config.name) {
+            Ok(remote) => remote,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        if remote_config.push_url.is_some() && remote.pushurl() != remote_config.push_url.as_deref()
+        {
+            return Ok(false);
+        }
+    }
+
+    // Don't use calculate_head(), it asks the remote for its default branch.
+    let head = match repo.head() {
+        Ok(head) if !repo.head_detached()? => head,
+        _ => return Ok(false),
+    };
+    let short_branch = match &git_config.branch {
+        Some(branch_name) => shorten_branch_ref(branch_name),
+        None => head.shorthand().ok_or(E::InvalidBranchError)?,
+    };
+    if head.name() != Some(&format!("refs/heads/{}", short_branch)) {
+        return Ok(false);
+    }
+
+    let user_git_config = open_git_config(&git_path)?;
+    let merge_branch = match get_push_branch(&repo, short_branch, &user_git_config)? {
+        Some(push_branch) => push_branch,
+        None => match repo
+            .find_branch(short_branch, BranchType::Local)?
+            .upstream()
+        {
+            Ok(upstream_branch) => upstream_branch,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        },
+    };
+    let head_oid = head.peel_to_commit()?.id();
+    let merge_oid = merge_branch.get().peel_to_commit()?.id();
+    Ok(head_oid == merge_oid || repo.graph_descendant_of(head_oid, merge_oid)?)
+}
+
+/// The global, XDG, and system git config files, and then the repo's own one
+/// if it exists.
+fn open_git_config(git_path: &Path) -> Result<git2::Config> {
+    // Opens the global, XDG, and system files in order.
+    let mut user_git_config = git2::Config::open_default()?;
+    // Then add the local one if defined.
+    let local_git_config_path = git_path.join(".git/config");
+    if local_git_config_path.exists() {
+        user_git_config.add_file(&local_git_config_path, ConfigLevel::Local, false)?;
+    }
+    Ok(user_git_config)
+}
+
 fn set_up_remote(repo: &Repository, remote_config: &GitRemote) -> Result<()> {
     let remote_name = &remote_config.name;
 
@@ -168,13 +240,30 @@ fn set_up_remote(repo: &Repository, remote_config: &GitRemote) -> Result<()> {
     }
     let fetch_refspecs: [&str; 0] = [];
     {
+        // Show fetch progress in the live display, if it's shown.
+        let fetch_bar = progress::fetch_bar(&format!(
+            "{} {}",
+            repo.workdir().unwrap_or_else(|| repo.path()).display(),
+            remote_name
+        ));
         let mut count = 0;
-        remote
-            .fetch(
-                &fetch_refspecs,
-                Some(FetchOptions::new().remote_callbacks(remote_callbacks(&mut count))),
-                Some("up-rs automated fetch"),
-            )
+        let mut callbacks = remote_callbacks(&mut count);
+        if let Some(bar) = &fetch_bar {
+            callbacks.transfer_progress(move |stats| {
+                bar.set_length(stats.total_objects() as u64);
+                bar.set_position(stats.received_objects() as u64);
+                true
+            });
+        }
+        let fetch_result = remote.fetch(
+            &fetch_refspecs,
+            Some(FetchOptions::new().remote_callbacks(callbacks)),
+            Some("up-rs automated fetch"),
+        );
+        if let Some(bar) = &fetch_bar {
+            bar.finish_and_clear();
+        }
+        fetch_result
             .map_err(|e| {
                 let extra_info = if e.to_string()
                     == "failed to acquire username/password from local configuration"
@@ -0,0 +1,349 @@
+//! Show how tasks require each other, with `up graph`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::Write,
+};
+
+use anyhow::Result;
+use displaydoc::Display;
+use thiserror::Error;
+
+use crate::{args::GraphFormat, config::UpConfig, tasks};
+
+/// Why a task would be run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    /// Runs by default (`auto_run` isn't false).
+    AutoRun,
+    /// Doesn't auto-run, but is required by a task that does (or by a bootstrap
+    /// task).
+    Dependency,
+    /// Only runs if passed to `--tasks` or listed in `bootstrap_tasks`.
+    NotAutoRun,
+}
+
+impl Role {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::AutoRun => "auto-run",
+            Self::Dependency => "dependency",
+            Self::NotAutoRun => "not auto-run",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    role: Role,
+    requires: Vec<String>,
+    /// Position in `bootstrap_tasks` (starting at 1), if listed.
+    bootstrap: Option<usize>,
+}
+
+/// The tasks and what they require.
+#[derive(Debug)]
+struct Graph {
+    nodes: BTreeMap<String, Node>,
+    bootstrap_tasks: Vec<String>,
+}
+
+/// Print the `requires` graph of the tasks in the `tasks_dirname` directory and
+/// the up config.
+/// Errors after printing if there are cycles or requires of tasks that don't
+/// exist.
+pub fn run(config: &UpConfig, tasks_dirname: &str, format: GraphFormat) -> Result<()> {
+    let tasks_dir = tasks::tasks_dir(config, tasks_dirname)?;
+    let bootstrap_tasks = config
+        .config_toml
+        .bootstrap_tasks
+        .clone()
+        .unwrap_or_default();
+
+    let mut tasks = Vec::new();
+    for (_, task) in tasks::load_tasks(config, &tasks_dir)? {
+        let task = task?;
+        tasks.push((
+            task.name,
+            task.config.auto_run.unwrap_or(true),
+            task.config.requires.unwrap_or_default(),
+        ));
+    }
+    let graph = Graph::new(tasks, bootstrap_tasks);
+
+    let cycles = graph.cycles();
+    let missing = graph.missing();
+    match format {
+        GraphFormat::Dot => print!("{}", graph.to_dot(&cycles)),
+        GraphFormat::Tree => {
+            print!("{}", graph.to_tree());
+            if !cycles.is_empty() {
+                println!("\nCycles:");
+                for cycle in &cycles {
+                    println!("- {}", cycle.join(" -> "));
+                }
+            }
+            if !missing.is_empty() {
+                println!("\nMissing tasks:");
+                for (from, required) in &missing {
+                    match from {
+                        Some(from) => println!("- '{}' requires '{}'", from, required),
+                        None => println!("- bootstrap_tasks includes '{}'", required),
+                    }
+                }
+            }
+        }
+    }
+
+    if !cycles.is_empty() || !missing.is_empty() {
+        return Err(GraphError::InvalidGraph {
+            cycles: cycles.len(),
+            missing: missing_names(&missing).len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+impl Graph {
+    /// Build the graph from each task's name, `auto_run` and `requires`.
+    fn new(tasks: Vec<(String, bool, Vec<String>)>, bootstrap_tasks: Vec<String>) -> Self {
+        let mut nodes: BTreeMap<String, Node> = tasks
+            .into_iter()
+            .map(|(name, auto_run, requires)| {
+                let bootstrap = bootstrap_tasks
+                    .iter()
+                    .position(|b| b == &name)
+                    .map(|i| i + 1);
+                let role = if auto_run {
+                    Role::AutoRun
+                } else {
+                    Role::NotAutoRun
+                };
+                (
+                    name,
+                    Node {
+                        role,
+                        requires,
+                        bootstrap,
+                    },
+                )
+            })
+            .collect();
+
+        // Anything required by a task that will be run is pulled in as a dependency.
+        let mut to_visit: Vec<String> = nodes
+            .iter()
+            .filter(|(_, node)| node.role == Role::AutoRun || node.bootstrap.is_some())
+            .flat_map(|(_, node)| node.requires.clone())
+            .collect();
+        let mut visited = HashSet::new();
+        while let Some(name) = to_visit.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if let Some(node) = nodes.get_mut(&name) {
+                if node.role == Role::NotAutoRun {
+                    node.role = Role::Dependency;
+                }
+                to_visit.extend(node.requires.iter().cloned());
+            }
+        }
+
+        Self {
+            nodes,
+            bootstrap_tasks,
+        }
+    }
+
+    /// Requires (and bootstrap tasks) that point at tasks that don't exist, as
+    /// `(requiring task, missing task)`. The requiring task is `None` for
+    /// bootstrap tasks.
+    fn missing(&self) -> Vec<(Option<String>, String)> {
+        let mut missing: Vec<(Option<String>, String)> = self
+            .bootstrap_tasks
+            .iter()
+            .filter(|name| !self.nodes.contains_key(*name))
+            .map(|name| (None, name.clone()))
+            .collect();
+        for (name, node) in &self.nodes {
+            for required in &node.requires {
+                if !self.nodes.contains_key(required) {
+                    missing.push((Some(name.clone()), required.clone()));
+                }
+            }
+        }
+        missing
+    }
+
+    /// Every cycle in the requires graph, each starting and ending with the same
+    /// task.
+    fn cycles(&self) -> Vec<Vec<String>> {
+        tasks::requires_cycles(self.nodes.keys(), |name| self.requires(name))
+    }
+
+    fn requires(&self, name: &str) -> &[String] {
+        self.nodes
+            .get(name)
+            .map_or(&[], |node| node.requires.as_slice())
+    }
+
+    /// Description of a task for the tree and DOT output.
+    fn annotation(&self, name: &str) -> String {
+        let node = match self.nodes.get(name) {
+            Some(node) => node,
+            None => return "not found".to_owned(),
+        };
+        let mut annotation = node.role.name().to_owned();
+        if let Some(position) = node.bootstrap {
+            let _ = write!(annotation, ", bootstrap {}", position);
+        }
+        annotation
+    }
+
+    /// DOT label for a node, the task name with its annotation underneath.
+    fn dot_label(&self, name: &str) -> String {
+        format!(
+            "\"{}\\n{}\"",
+            dot_escape(name),
+            dot_escape(&self.annotation(name))
+        )
+    }
+
+    /// Indented tree of tasks and what they require. Tasks that nothing requires
+    /// are at the top level, and tasks that have already been shown are only
+    /// expanded once.
+    fn to_tree(&self) -> String {
+        fn add_subtree(
+            name: &str,
+            depth: usize,
+            graph: &Graph,
+            shown: &mut HashSet<String>,
+            path: &mut Vec<String>,
+            out: &mut String,
+        ) {
+            let _ = write!(
+                out,
+                "{}{} [{}]",
+                "  ".repeat(depth),
+                name,
+                graph.annotation(name)
+            );
+            if path.iter().any(|n| n == name) {
+                out.push_str(" (cycle)\n");
+                return;
+            }
+            if graph.requires(name).is_empty() || shown.insert(name.to_owned()) {
+                out.push('\n');
+            } else {
+                out.push_str(" (see above)\n");
+                return;
+            }
+            path.push(name.to_owned());
+            for required in graph.requires(name) {
+                add_subtree(required, depth + 1, graph, shown, path, out);
+            }
+            path.pop();
+        }
+
+        let mut out = String::new();
+        if !self.bootstrap_tasks.is_empty() {
+            let _ = writeln!(
+                out,
+                "Bootstrap order: {}\n",
+                self.bootstrap_tasks.join(" -> ")
+            );
+        }
+
+        let required: BTreeSet<&String> = self
+            .nodes
+            .values()
+            .flat_map(|node| &node.requires)
+            .collect();
+        let mut shown = HashSet::new();
+        for name in self.nodes.keys().filter(|n| !required.contains(n)) {
+            add_subtree(name, 0, self, &mut shown, &mut Vec::new(), &mut out);
+        }
+        // Tasks that are only reachable from a cycle.
+        for name in self.nodes.keys() {
+            if !shown.contains(name) && !self.requires(name).is_empty() {
+                add_subtree(name, 0, self, &mut shown, &mut Vec::new(), &mut out);
+            }
+        }
+        out
+    }
+
+    /// Graphviz DOT graph, with edges from each task to the tasks it requires.
+    fn to_dot(&self, cycles: &[Vec<String>]) -> String {
+        let cycle_edges: HashSet<(&str, &str)> = cycles
+            .iter()
+            .flat_map(|cycle| cycle.windows(2).map(|w| (w[0].as_str(), w[1].as_str())))
+            .collect();
+
+        let mut out = String::from("digraph tasks {\n");
+        for (name, node) in &self.nodes {
+            let style = match node.role {
+                Role::AutoRun => "solid",
+                Role::Dependency => "dashed",
+                Role::NotAutoRun => "dotted",
+            };
+            let _ = writeln!(
+                out,
+                "    {} [label={}, style={}];",
+                dot_quote(name),
+                self.dot_label(name),
+                style
+            );
+        }
+        // Each missing task once, however many tasks require it.
+        for missing in missing_names(&self.missing()) {
+            let _ = writeln!(
+                out,
+                "    {} [label={}, color=red, fontcolor=red];",
+                dot_quote(missing),
+                self.dot_label(missing)
+            );
+        }
+        for (name, node) in &self.nodes {
+            for required in &node.requires {
+                let _ = write!(out, "    {} -> {}", dot_quote(name), dot_quote(required));
+                if cycle_edges.contains(&(name.as_str(), required.as_str())) {
+                    out.push_str(" [color=red]");
+                }
+                out.push_str(";\n");
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// The distinct names of the `missing` tasks, see [`Graph::missing()`].
+fn missing_names(missing: &[(Option<String>, String)]) -> BTreeSet<&str> {
+    missing.iter().map(|(_, name)| name.as_str()).collect()
+}
+
+/// Quote `s` as a DOT ID.
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", dot_escape(s))
+}
+
+/// Escape `s` for use inside a quoted DOT string. Only quotes and
+/// backslashes need escaping, everything else is used as is.
+fn dot_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum GraphError {
+    /// Found {cycles} requires cycle(s) and {missing} missing task(s).
+    InvalidGraph { cycles: usize, missing: usize },
+}
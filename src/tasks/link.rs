@@ -71,13 +71,7 @@ pub(crate) fn run(config: LinkOptions) -> Result<()> {
             .collect::<Result<Vec<_>>>()
     );
 
-    // For each non-directory file in from_dir.
-    for from_path in WalkDir::new(&from_dir)
-        .min_depth(1)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|f| !f.file_type().is_dir())
-    {
+    for from_path in files_to_link(&from_dir) {
         let rel_path = from_path.path().strip_prefix(&from_dir)?;
         create_parent_dir(&to_dir, rel_path, &backup_dir)?;
         link_path(&from_path, &to_dir, rel_path, &backup_dir)?;
@@ -110,6 +104,36 @@ pub(crate) fn run(config: LinkOptions) -> Result<()> {
     Ok(())
 }
 
+/// Whether everything in `from_dir` is already linked into `to_dir`, so
+/// [`run()`] wouldn't change any links. Doesn't change anything, for dry runs.
+pub(crate) fn is_up_to_date(config: &LinkOptions) -> Result<bool> {
+    // Leave reporting missing directories to the run.
+    let (from_dir, to_dir) = match (
+        resolve_directory(PathBuf::from(&config.from_dir), "From"),
+        resolve_directory(PathBuf::from(&config.to_dir), "To"),
+    ) {
+        (Ok(from_dir), Ok(to_dir)) => (from_dir, to_dir),
+        _ => return Ok(false),
+    };
+    for from_path in files_to_link(&from_dir) {
+        let to_path = to_dir.join(from_path.path().strip_prefix(&from_dir)?);
+        if to_path.read_link().ok().as_deref() != Some(from_path.path()) {
+            debug!("Link at {:?} needs updating.", to_path);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Each non-directory file in `from_dir`.
+fn files_to_link(from_dir: &Path) -> impl Iterator<Item = DirEntry> {
+    WalkDir::new(from_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|f| !f.file_type().is_dir())
+}
+
 /// Ensure dir exists, and resolve symlinks to find it's canonical path.
 fn resolve_directory(dir_path: PathBuf, name: &str) -> Result<PathBuf> {
     ensure!(
@@ -0,0 +1,173 @@
+//! Show the tasks defined in the config, with `up list`.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Result;
+use displaydoc::Display;
+use serde_derive::Serialize;
+use thiserror::Error;
+
+use crate::{
+    config::UpConfig,
+    tasks::{self, task::Task},
+};
+
+/// Everything `up list --json` prints.
+#[derive(Debug, Serialize)]
+struct Listing {
+    tasks: Vec<TaskListing>,
+    errors: Vec<TaskFileError>,
+}
+
+/// A task as defined in its task file.
+#[derive(Debug, Serialize)]
+struct TaskListing {
+    name: String,
+    description: Option<String>,
+    path: PathBuf,
+    run_lib: Option<String>,
+    check_cmd: Option<Vec<String>>,
+    run_cmd: Option<Vec<String>>,
+    auto_run: bool,
+    requires: Vec<String>,
+    constraints: BTreeMap<String, String>,
+    bootstrap: bool,
+}
+
+/// A task file that couldn't be parsed.
+#[derive(Debug, Serialize)]
+struct TaskFileError {
+    path: PathBuf,
+    error: String,
+}
+
+impl TaskListing {
+    fn new(task: Task, bootstrap_tasks: &[String]) -> Self {
+        let config = task.config;
+        Self {
+            bootstrap: bootstrap_tasks.contains(&task.name),
+            name: task.name,
+            description: config.description,
+            path: task.path,
+            run_lib: config.run_lib,
+            check_cmd: config.check_cmd,
+            run_cmd: config.run_cmd,
+            auto_run: config.auto_run.unwrap_or(true),
+            requires: config.requires.unwrap_or_default(),
+            constraints: config.constraints.unwrap_or_default().into_iter().collect(),
+        }
+    }
+
+    /// What the task runs, for the table.
+    fn runs(&self) -> String {
+        match (&self.run_lib, &self.run_cmd) {
+            (Some(lib), _) => format!("run_lib: {}", lib),
+            (None, Some(cmd)) => cmd.join(" "),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Print the tasks in the `tasks_dirname` directory and the up config as a
+/// table, or as JSON if `json` is set.
+///
+/// Tasks that fail to load are listed too, and cause an error once everything
+/// has been printed.
+pub fn run(config: &UpConfig, tasks_dirname: &str, json: bool) -> Result<()> {
+    let tasks_dir = tasks::tasks_dir(config, tasks_dirname)?;
+    let bootstrap_tasks = config
+        .config_toml
+        .bootstrap_tasks
+        .clone()
+        .unwrap_or_default();
+
+    let mut listing = Listing {
+        tasks: Vec::new(),
+        errors: Vec::new(),
+    };
+    for (path, task) in tasks::load_tasks(config, &tasks_dir)? {
+        match task {
+            Ok(task) => listing.tasks.push(TaskListing::new(task, &bootstrap_tasks)),
+            Err(e) => listing.errors.push(TaskFileError {
+                path,
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+    listing.tasks.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&listing)?);
+    } else {
+        print_table(&listing);
+    }
+
+    if !listing.errors.is_empty() {
+        return Err(ListError::InvalidTaskFiles {
+            count: listing.errors.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Print the tasks as a table, followed by any errors.
+fn print_table(listing: &Listing) {
+    let mut rows = vec![[
+        "NAME",
+        "RUNS",
+        "AUTO_RUN",
+        "BOOTSTRAP",
+        "REQUIRES",
+        "CONSTRAINTS",
+        "FILE",
+        "DESCRIPTION",
+    ]
+    .map(ToOwned::to_owned)];
+    for task in &listing.tasks {
+        rows.push([
+            task.name.clone(),
+            task.runs(),
+            task.auto_run.to_string(),
+            task.bootstrap.to_string(),
+            task.requires.join(", "),
+            task.constraints
+                .iter()
+                .map(|(k, v)| format!("{} = {}", k, v))
+                .collect::<Vec<_>>()
+                .join(", "),
+            task.path.display().to_string(),
+            task.description.clone().unwrap_or_default(),
+        ]);
+    }
+
+    let mut widths = [0; 8];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+
+    if !listing.errors.is_empty() {
+        println!("\nFailed to parse:");
+        for error in &listing.errors {
+            println!("- {}: {}", error.path.display(), error.error);
+        }
+    }
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum ListError {
+    /// Failed to parse {count} task file(s).
+    InvalidTaskFiles { count: usize },
+}
@@ -0,0 +1,162 @@
+//! External run libraries, `up-<name>` executables.
+//!
+//! A task with `run_lib = "foo"`, where `foo` isn't a registered
+//! [`RunLib`](crate::tasks::run_lib::RunLib), runs an `up-foo` executable from
+//! the up.toml's `plugin_dir` or the task's `PATH`.
+//!
+//! The plugin is run twice, first as `up-foo check` and then (if the check
+//! says something would change) as `up-foo run`, with the task's `data` (env
+//! vars expanded) as JSON on stdin. In both modes it reports what happened
+//! either by printing a JSON result on stdout, e.g.
+//! `{"status": "unchanged", "message": "Already up to date."}`, or with its
+//! exit code:
+//!
+//! | Status      | Exit code | Check mode               | Run mode                    |
+//! | ---         | ---       | ---                      | ---                         |
+//! | `changed`   | 0         | the task needs to run    | the task made changes       |
+//! | `unchanged` | 100       | up to date, task skipped | nothing to do, skipped      |
+//! | `failed`    | any other | the task fails           | the task fails (or retries) |
+//!
+//! A plugin that can't tell whether anything would change should report
+//! `changed` in check mode.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    process::Output,
+};
+
+use anyhow::Result;
+use serde_derive::Deserialize;
+
+use crate::{
+    config::UpConfig,
+    tasks::{constraints, ResolveEnv},
+};
+
+/// Prefix of plugin executable names.
+const PLUGIN_PREFIX: &str = "up-";
+
+/// Exit code a plugin uses to report that nothing changed (or would change).
+pub const UNCHANGED_EXIT_CODE: i32 = 100;
+
+/// Which plugin command is being run, passed as its first argument.
+#[derive(Debug, Clone, Copy)]
+pub enum Mode {
+    /// Check whether the task needs to run.
+    Check,
+    /// Run the task.
+    Run,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Check => write!(f, "check"),
+            Self::Run => write!(f, "run"),
+        }
+    }
+}
+
+/// What a plugin reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Something changed (run mode) or would change (check mode).
+    Changed,
+    /// Nothing changed or would change.
+    Unchanged,
+    /// The plugin failed.
+    Failed,
+}
+
+/// Result a plugin can print as JSON on stdout instead of using its exit code.
+#[derive(Debug, Deserialize)]
+pub struct PluginResult {
+    pub status: Status,
+    /// Explanation to log (or include in the error if the plugin failed).
+    pub message: Option<String>,
+}
+
+impl PluginResult {
+    /// The result of a plugin command, from the JSON it printed or else its
+    /// exit code.
+    pub(crate) fn from_output(output: &Output) -> Self {
+        if let Ok(result) = serde_json::from_slice::<Self>(&output.stdout) {
+            return result;
+        }
+        let status = match output.status.code() {
+            Some(0) => Status::Changed,
+            Some(UNCHANGED_EXIT_CODE) => Status::Unchanged,
+            _ => Status::Failed,
+        };
+        Self {
+            status,
+            message: None,
+        }
+    }
+}
+
+/// Expand env vars in every string in the plugin's data.
+impl ResolveEnv for toml::Value {
+    fn resolve_env<F>(&mut self, env_fn: F) -> Result<()>
+    where
+        F: Fn(&str) -> Result<String>,
+    {
+        resolve_value(self, &env_fn)
+    }
+}
+
+fn resolve_value(value: &mut toml::Value, env_fn: &dyn Fn(&str) -> Result<String>) -> Result<()> {
+    match value {
+        toml::Value::String(s) => *s = env_fn(s)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                resolve_value(value, env_fn)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                resolve_value(value, env_fn)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The up.toml's `plugin_dir`, relative to the directory containing the
+/// up.toml.
+pub(crate) fn plugin_dir<F>(config: &UpConfig, env_fn: F) -> Result<Option<PathBuf>>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    let dir = match &config.config_toml.plugin_dir {
+        Some(dir) => PathBuf::from(env_fn(dir)?),
+        None => return Ok(None),
+    };
+    Ok(Some(
+        match config.up_toml_path.as_ref().and_then(|path| path.parent()) {
+            Some(up_dir) => up_dir.join(dir),
+            None => dir,
+        },
+    ))
+}
+
+/// Find the `up-<name>` executable in `plugin_dir`, or else on the task
+/// `PATH`.
+pub(crate) fn find(
+    name: &str,
+    plugin_dir: Option<&Path>,
+    env: &HashMap<String, String>,
+) -> Option<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    let file_name = format!("{}{}", PLUGIN_PREFIX, name);
+    plugin_dir
+        .map(|dir| dir.join(&file_name))
+        .filter(|path| constraints::is_executable(path))
+        .or_else(|| constraints::find_executable(&file_name, env))
+}
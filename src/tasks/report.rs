@@ -0,0 +1,133 @@
+//! Machine-readable report of a run, written with `up run --report <path>`.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use displaydoc::Display;
+use log::debug;
+use serde_derive::Serialize;
+use thiserror::Error;
+
+use crate::tasks::task::{Task, TaskStatus};
+
+/// Results of a run.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    /// Whether this was a dry run (only check commands were run).
+    pub dry_run: bool,
+    /// Every task that was run or checked, sorted by name.
+    pub tasks: Vec<TaskReport>,
+    /// Tasks that would have been run, but weren't started because a failure
+    /// stopped the run, sorted by name.
+    pub not_started: Vec<String>,
+    /// The error that stopped the run itself, and its causes, outermost first.
+    /// Empty unless the run failed other than by a task failing, e.g. because
+    /// of a requires cycle. If that happened before any task was started,
+    /// `tasks` and `not_started` are empty too.
+    pub errors: Vec<String>,
+}
+
+/// Result of a single task.
+#[derive(Debug, Serialize)]
+pub struct TaskReport {
+    /// Task name.
+    pub name: String,
+    /// Path to the file the task was defined in.
+    pub path: PathBuf,
+    /// Run library the task used, if any.
+    pub run_lib: Option<String>,
+    /// Check command from the task config, before env vars were expanded.
+    pub check_cmd: Option<Vec<String>>,
+    /// Run command from the task config, before env vars were expanded.
+    pub run_cmd: Option<Vec<String>>,
+    /// Final status: `passed`, `failed` or `skipped`.
+    pub status: &'static str,
+    /// Exit code of the run command, if it ran and exited normally.
+    pub exit_code: Option<i32>,
+    /// Number of times the run command or run library was started.
+    pub attempts: u32,
+    /// How long the check command took in seconds, if it was run.
+    pub check_duration_secs: Option<f64>,
+    /// How long the run command or run library took in seconds, if it was run.
+    pub run_duration_secs: Option<f64>,
+    /// The error and its causes, outermost first. Empty unless the task failed.
+    pub errors: Vec<String>,
+}
+
+impl From<&Task> for TaskReport {
+    fn from(task: &Task) -> Self {
+        Self {
+            name: task.name.clone(),
+            path: task.path.clone(),
+            run_lib: task.config.run_lib.clone(),
+            check_cmd: task.config.check_cmd.clone(),
+            run_cmd: task.config.run_cmd.clone(),
+            status: status_name(&task.status),
+            exit_code: task.exit_code,
+            attempts: task.attempts,
+            check_duration_secs: task.check_duration.as_ref().map(Duration::as_secs_f64),
+            run_duration_secs: task.run_duration.as_ref().map(Duration::as_secs_f64),
+            errors: match &task.status {
+                TaskStatus::Failed(e) => e.chain().map(ToString::to_string).collect(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+/// Write a JSON report of every task that was started to `path`, and the error
+/// that stopped the run, if any.
+pub(crate) fn write(
+    path: &Path,
+    tasks: &HashMap<String, Task>,
+    not_started: &[String],
+    dry_run: bool,
+    error: Option<&anyhow::Error>,
+) -> Result<()> {
+    let mut task_reports: Vec<TaskReport> = tasks
+        .values()
+        .filter(|task| !matches!(task.status, TaskStatus::New))
+        .filter(|task| !not_started.contains(&task.name))
+        .map(TaskReport::from)
+        .collect();
+    task_reports.sort_by(|a, b| a.name.cmp(&b.name));
+    let report = RunReport {
+        dry_run,
+        tasks: task_reports,
+        not_started: not_started.to_vec(),
+        errors: error.map_or_else(Vec::new, |e| e.chain().map(ToString::to_string).collect()),
+    };
+
+    debug!("Writing run report to {}", path.display());
+    let json = serde_json::to_string_pretty(&report)?;
+    fs::write(path, json).map_err(|e| ReportError::WriteReport {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    Ok(())
+}
+
+/// Lowercase name for a task status.
+pub(crate) const fn status_name(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::New => "new",
+        TaskStatus::Blocked => "blocked",
+        TaskStatus::Queued => "queued",
+        TaskStatus::Running => "running",
+        TaskStatus::Skipped => "skipped",
+        TaskStatus::Passed => "passed",
+        TaskStatus::Failed(_) => "failed",
+    }
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum ReportError {
+    /// Failed to write run report to '{path}'.
+    WriteReport { path: PathBuf, source: io::Error },
+}
@@ -0,0 +1,491 @@
+//! Libraries that tasks can use with `run_lib = "<name>"` instead of a check
+//! and run command, e.g. `link` or `git`.
+//!
+//! The built-in libraries are always registered. Programs embedding `up_rs`
+//! can add their own (or replace a built-in one) by implementing [`RunLib`]
+//! and passing it to [`register()`] before running any tasks. A `run_lib` that
+//! isn't registered runs an external plugin instead, see
+//! [`plugin`](crate::tasks::plugin).
+
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{Arc, LazyLock, PoisonError, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use displaydoc::Display;
+use log::info;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::{
+    args::{GenerateGitConfig, LinkOptions, UpdateSelfOptions},
+    generate,
+    tasks::{
+        self,
+        defaults::DefaultsConfig,
+        git::GitConfig,
+        task::{Abort, Task, TaskStatus},
+        ResolveEnv,
+    },
+};
+
+/// A library that tasks can run by setting `run_lib` to its [`name()`], with
+/// the library's settings in the task's `data`.
+///
+/// [`name()`]: RunLib::name
+pub trait RunLib: Send + Sync {
+    /// The task's `data`, parsed and with env vars expanded.
+    type Data: DeserializeOwned + ResolveEnv + Debug + Clone;
+
+    /// Name that tasks use in their `run_lib` field.
+    fn name(&self) -> &str;
+
+    /// Data to use for tasks that don't set any. The default is to fail the
+    /// task, as its `data` is required.
+    fn default_data(&self) -> Option<Self::Data> {
+        None
+    }
+
+    /// Whether the task is already up to date, in which case it is skipped
+    /// rather than run. The default is to always run it.
+    fn check(&self, _data: &Self::Data) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Used instead of [`check()`] in dry-run mode, so must not change
+    /// anything. Libraries whose check isn't read-only (e.g. it fetches) can
+    /// guess from local state here. The default is to call `check()`.
+    ///
+    /// [`check()`]: RunLib::check
+    fn probe(&self, data: &Self::Data) -> Result<bool> {
+        self.check(data)
+    }
+
+    /// Run the library with the task's data. Called again if it fails and the
+    /// task has `retries` left.
+    fn run(&self, data: Self::Data) -> Result<()>;
+}
+
+/// Object-safe wrapper around [`RunLib`], so libraries with different `Data`
+/// can be stored together.
+trait AnyRunLib: Send + Sync {
+    fn run_task(
+        &self,
+        task: &mut Task,
+        env_fn: &dyn Fn(&str) -> Result<String>,
+        dry_run: bool,
+        abort: &Abort,
+    ) -> Result<()>;
+}
+
+impl<L: RunLib> AnyRunLib for L {
+    fn run_task(
+        &self,
+        task: &mut Task,
+        env_fn: &dyn Fn(&str) -> Result<String>,
+        dry_run: bool,
+        abort: &Abort,
+    ) -> Result<()> {
+        let data = match task.data::<L::Data, _>(env_fn)? {
+            Some(data) => data,
+            None => self
+                .default_data()
+                .ok_or_else(|| anyhow!("Task '{}' data had no value.", &task.name))?,
+        };
+        let up_to_date = if dry_run {
+            self.probe(&data)?
+        } else {
+            self.check(&data)?
+        };
+        if up_to_date {
+            info!(
+                "Skipping task '{}' as run_lib '{}' is up to date.",
+                &task.name,
+                self.name()
+            );
+            task.status = TaskStatus::Skipped;
+            return Ok(());
+        }
+        task.run_lib(self.name(), data, dry_run, abort, |data| self.run(data))
+    }
+}
+
+/// Registered libraries by name, starting with the built-in ones.
+static REGISTRY: LazyLock<RwLock<BTreeMap<String, Arc<dyn AnyRunLib>>>> = LazyLock::new(|| {
+    let mut libs = BTreeMap::new();
+    insert(&mut libs, Link);
+    insert(&mut libs, Git);
+    insert(&mut libs, GenerateGit);
+    insert(&mut libs, Defaults);
+    insert(&mut libs, UpdateSelf);
+    RwLock::new(libs)
+});
+
+/// Add `lib` to `libs`, returning whether it replaced one with the same name.
+fn insert(libs: &mut BTreeMap<String, Arc<dyn AnyRunLib>>, lib: impl RunLib + 'static) -> bool {
+    libs.insert(lib.name().to_owned(), Arc::new(lib)).is_some()
+}
+
+/// Make `lib` available to tasks, replacing any library with the same name.
+/// Returns whether a library was replaced.
+pub fn register(lib: impl RunLib + 'static) -> bool {
+    insert(
+        &mut REGISTRY.write().unwrap_or_else(PoisonError::into_inner),
+        lib,
+    )
+}
+
+/// Names of the registered libraries, sorted.
+#[must_use]
+pub fn names() -> Vec<String> {
+    REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .keys()
+        .cloned()
+        .collect()
+}
+
+/// Whether a library called `name` is registered.
+pub(crate) fn is_registered(name: &str) -> bool {
+    REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .contains_key(name)
+}
+
+/// Run `task` with its `run_lib`, or in dry-run mode log what would have been
+/// run.
+pub(crate) fn run_task(
+    lib: &str,
+    task: &mut Task,
+    env_fn: &dyn Fn(&str) -> Result<String>,
+    dry_run: bool,
+    abort: &Abort,
+) -> Result<()> {
+    // Don't hold the lock while the library runs.
+    let run_lib = REGISTRY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(lib)
+        .cloned();
+    match run_lib {
+        Some(run_lib) => run_lib.run_task(task, env_fn, dry_run, abort),
+        None => Err(RunLibError::Unknown {
+            task: task.name.clone(),
+            lib: lib.to_owned(),
+            registered: names().join(", "),
+        }
+        .into()),
+    }
+}
+
+/// Symlink files from one directory into another.
+struct Link;
+
+impl RunLib for Link {
+    type Data = LinkOptions;
+
+    fn name(&self) -> &'static str {
+        "link"
+    }
+
+    /// Only in dry-run mode, real runs still create the backup dir and warn
+    /// about anything left in it.
+    fn probe(&self, data: &Self::Data) -> Result<bool> {
+        tasks::link::is_up_to_date(data)
+    }
+
+    fn run(&self, data: Self::Data) -> Result<()> {
+        tasks::link::run(data)
+    }
+}
+
+/// Clone or update git repos.
+struct Git;
+
+impl RunLib for Git {
+    type Data = Vec<GitConfig>;
+
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    /// Only in dry-run mode, as it can't tell whether there's anything new to
+    /// fetch.
+    fn probe(&self, data: &Self::Data) -> Result<bool> {
+        for config in data {
+            if !tasks::git::update::is_up_to_date(config)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn run(&self, data: Self::Data) -> Result<()> {
+        tasks::git::run(data)
+    }
+}
+
+/// Generate git task configs from existing repos.
+struct GenerateGit;
+
+impl RunLib for GenerateGit {
+    type Data = Vec<GenerateGitConfig>;
+
+    fn name(&self) -> &'static str {
+        "generate_git"
+    }
+
+    fn run(&self, data: Self::Data) -> Result<()> {
+        generate::git::run(&data)
+    }
+}
+
+/// Set macOS user defaults.
+struct Defaults;
+
+impl RunLib for Defaults {
+    type Data = DefaultsConfig;
+
+    fn name(&self) -> &'static str {
+        "defaults"
+    }
+
+    fn run(&self, data: Self::Data) -> Result<()> {
+        tasks::defaults::run(data)
+    }
+}
+
+/// Update the up binary itself.
+struct UpdateSelf;
+
+impl RunLib for UpdateSelf {
+    type Data = UpdateSelfOptions;
+
+    fn name(&self) -> &'static str {
+        "self"
+    }
+
+    fn default_data(&self) -> Option<Self::Data> {
+        Some(UpdateSelfOptions::default())
+    }
+
+    fn run(&self, data: Self::Data) -> Result<()> {
+        tasks::update_self::run(&data)
+    }
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum RunLibError {
+    /// No run library or up-{lib} plugin for task '{task}', registered libraries: {registered}.
+    Unknown {
+        task: String,
+        lib: String,
+        registered: String,
+    },
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod run_lib_tests {
+    use std::{
+        collections::HashMap,
+        path::Path,
+        sync::{mpsc, Mutex, PoisonError},
+        thread,
+        time::Duration,
+    };
+
+    use anyhow::Result;
+
+    use super::RunLib;
+    use crate::{
+        config::UpConfig,
+        tasks::{
+            self,
+            task::{Abort, Task, TaskConfig, TaskStatus},
+        },
+    };
+
+    /// Records the data it was run with.
+    struct Recorder {
+        name: &'static str,
+        up_to_date: bool,
+        ran: Mutex<Vec<toml::Value>>,
+    }
+
+    impl Recorder {
+        const fn new(name: &'static str, up_to_date: bool) -> Self {
+            Self {
+                name,
+                up_to_date,
+                ran: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl RunLib for &'static Recorder {
+        type Data = toml::Value;
+
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn check(&self, _data: &Self::Data) -> Result<bool> {
+            Ok(self.up_to_date)
+        }
+
+        fn run(&self, data: Self::Data) -> Result<()> {
+            self.ran
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(data);
+            Ok(())
+        }
+    }
+
+    fn task(lib: &str) -> Task {
+        let config = TaskConfig {
+            run_lib: Some(lib.to_owned()),
+            data: Some(toml::Value::String("$GREETING".to_owned())),
+            ..TaskConfig::default()
+        };
+        Task::new("recorded".to_owned(), Path::new("up.toml"), config).unwrap()
+    }
+
+    /// A registered library should be run with the task's data, env vars
+    /// expanded, unless its check says it's up to date.
+    #[test]
+    fn register_and_run() {
+        static RUNS: Recorder = Recorder::new("test_run", false);
+        static SKIPS: Recorder = Recorder::new("test_skip", true);
+        assert!(!super::register(&RUNS));
+        assert!(!super::register(&SKIPS));
+        assert!(super::is_registered("test_run"));
+        assert!(super::names().contains(&"test_skip".to_owned()));
+
+        let mut env = HashMap::new();
+        env.insert("GREETING".to_owned(), "hello".to_owned());
+        let env_fn = tasks::env_fn(&env);
+        let abort = Abort::default();
+
+        let mut run_task = task("test_run");
+        super::run_task("test_run", &mut run_task, &env_fn, false, &abort).unwrap();
+        assert_eq!(
+            *RUNS.ran.lock().unwrap(),
+            vec![toml::Value::String("hello".to_owned())]
+        );
+
+        let mut skip_task = task("test_skip");
+        super::run_task("test_skip", &mut skip_task, &env_fn, false, &abort).unwrap();
+        assert!(matches!(skip_task.status, TaskStatus::Skipped));
+        assert!(SKIPS.ran.lock().unwrap().is_empty());
+
+        let mut dry_run_task = task("test_run");
+        super::run_task("test_run", &mut dry_run_task, &env_fn, true, &abort).unwrap();
+        assert_eq!(RUNS.ran.lock().unwrap().len(), 1);
+    }
+
+    /// Puts the built-in `self` library back when dropped, even if the test
+    /// using it fails, so other tests still get the real one.
+    struct RestoreUpdateSelf;
+
+    impl Drop for RestoreUpdateSelf {
+        fn drop(&mut self) {
+            super::register(super::UpdateSelf);
+        }
+    }
+
+    /// Registering a library with a built-in's name should replace it.
+    #[test]
+    fn replace_built_in() {
+        static REPLACEMENT: Recorder = Recorder::new("self", false);
+        assert!(super::is_registered("self"));
+        let _restore = RestoreUpdateSelf;
+        assert!(super::register(&REPLACEMENT));
+
+        let env = HashMap::new();
+        let mut task = task("self");
+        task.config.data = None;
+        let result = super::run_task(
+            "self",
+            &mut task,
+            &tasks::env_fn(&env),
+            false,
+            &Abort::default(),
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("data had no value"));
+    }
+
+    /// Panics in its check.
+    struct Panicker;
+
+    impl RunLib for Panicker {
+        type Data = toml::Value;
+
+        fn name(&self) -> &str {
+            "test_panic"
+        }
+
+        fn check(&self, _data: &Self::Data) -> Result<bool> {
+            panic!("test_panic check panicked");
+        }
+
+        fn run(&self, _data: Self::Data) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A library that panics should fail its task, rather than leaving the run
+    /// waiting for it forever.
+    #[test]
+    fn panic_fails_task() {
+        assert!(!super::register(Panicker));
+        let mut tasks = HashMap::new();
+        tasks.insert("recorded".to_owned(), task("test_panic"));
+        // Dry run, so the run isn't recorded in the state file.
+        let config = UpConfig {
+            dry_run: true,
+            ..UpConfig::default()
+        };
+        let (result_tx, result_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut env = HashMap::new();
+            env.insert("GREETING".to_owned(), "hello".to_owned());
+            let result = tasks::run_tasks(Vec::new(), tasks, &env, &config, Path::new("tasks"));
+            result_tx
+                .send(result.map_err(|e| format!("{:?}", e)))
+                .unwrap();
+        });
+        let error = result_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("Run didn't finish after the task panicked.")
+            .unwrap_err();
+        assert!(error.contains("Task 'recorded' panicked: test_panic check panicked"));
+    }
+
+    /// An unregistered library should fail the task, listing the ones that are.
+    #[test]
+    fn unknown() {
+        let env = HashMap::new();
+        let mut task = task("test_unknown");
+        let error = super::run_task(
+            "test_unknown",
+            &mut task,
+            &tasks::env_fn(&env),
+            false,
+            &Abort::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("No run library or up-test_unknown plugin for task 'recorded'"));
+        assert!(error.contains("link"));
+    }
+}
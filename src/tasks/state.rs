@@ -0,0 +1,231 @@
+//! Record of when each task last ran, kept between runs so tasks with a
+//! `run_interval` aren't run on every `up run`.
+//!
+//! Stored as JSON in `$XDG_STATE_HOME/up-rs/state.json`, or in the user cache
+//! dir (e.g. `~/.cache` or `~/Library/Caches`) if `XDG_STATE_HOME` isn't set.
+//! Tasks are keyed by their tasks dir as well as their name, so configs with
+//! tasks of the same name don't share a record. Runs update the file under a
+//! lock on `state.json.lock` next to it, so concurrent runs keep each other's
+//! records.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use displaydoc::Display;
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use self::StateError as E;
+
+/// Everything stored between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// The last run of each task, by tasks dir and then task name.
+    #[serde(default)]
+    pub task_dirs: BTreeMap<PathBuf, BTreeMap<String, TaskState>>,
+}
+
+/// The last run of a task.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskState {
+    /// When the task was started.
+    pub last_run: DateTime<Utc>,
+    /// How the run ended: `passed`, `failed` or `skipped`.
+    pub outcome: String,
+    /// When the task was last started in a run that passed, which may be
+    /// before `last_run` if later runs were skipped or failed.
+    #[serde(default)]
+    pub last_passed: Option<DateTime<Utc>>,
+    /// How long the check and run commands (or run library) took in seconds.
+    pub duration_secs: f64,
+}
+
+impl State {
+    /// Path of the state file, or `None` if there's no state or cache dir.
+    pub(crate) fn path() -> Option<PathBuf> {
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(dirs::cache_dir)
+            .map(|dir| dir.join("up-rs/state.json"))
+    }
+
+    /// Read the state file at `path`. A missing file is an empty state.
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(E::Read {
+                    path: path.to_owned(),
+                    source: e,
+                }
+                .into())
+            }
+        };
+        Ok(serde_json::from_str(&contents).map_err(|e| E::Parse {
+            path: path.to_owned(),
+            source: e,
+        })?)
+    }
+
+    /// Read the state file at `path`, warning and starting afresh if it can't be
+    /// read.
+    pub(crate) fn read_or_default(path: &Path) -> Self {
+        Self::read(path).unwrap_or_else(|e| {
+            warn!("Ignoring task state: {:#}", e);
+            Self::default()
+        })
+    }
+
+    /// Apply `update` to the state file at `path`. The file is re-read first,
+    /// under an exclusive lock held until it has been written, so records
+    /// written by other runs since this one read it aren't lost.
+    pub(crate) fn update<F>(path: &Path, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self),
+    {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| E::Write {
+                path: path.to_owned(),
+                source: e,
+            })?;
+        }
+        let _lock = lock(path)?;
+        let mut state = Self::read_or_default(path);
+        update(&mut state);
+        state.write(path)
+    }
+
+    /// Write the state file to `path`, whose directory must exist. The file is
+    /// replaced in one go, so an interrupted write (or another run reading it)
+    /// never sees half a file.
+    fn write(&self, path: &Path) -> Result<()> {
+        debug!("Writing task state to {}", path.display());
+        let json = serde_json::to_string_pretty(self)?;
+        // Unique per process, in case another run is saving at the same time.
+        let temp_path = path.with_extension(format!("json.{}.tmp", process::id()));
+        fs::write(&temp_path, json)
+            .and_then(|()| fs::rename(&temp_path, path))
+            .map_err(|e| {
+                // Best effort, the write error is the one worth reporting.
+                let _ = fs::remove_file(&temp_path);
+                E::Write {
+                    path: path.to_owned(),
+                    source: e,
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Record a run of task `name` from `tasks_dir`, keeping when it last
+    /// passed if this run didn't.
+    pub(crate) fn record(
+        &mut self,
+        tasks_dir: &Path,
+        name: String,
+        last_run: DateTime<Utc>,
+        outcome: &str,
+        duration: Duration,
+    ) {
+        let tasks = self.task_dirs.entry(tasks_dir.to_owned()).or_default();
+        let last_passed = if outcome == "passed" {
+            Some(last_run)
+        } else {
+            tasks
+                .get(&name)
+                .and_then(|task_state| task_state.last_passed)
+        };
+        tasks.insert(
+            name,
+            TaskState {
+                last_run,
+                outcome: outcome.to_owned(),
+                last_passed,
+                duration_secs: duration.as_secs_f64(),
+            },
+        );
+    }
+
+    /// How long ago task `name` from `tasks_dir` last passed, if it was within
+    /// `interval`.
+    pub(crate) fn passed_within(
+        &self,
+        tasks_dir: &Path,
+        name: &str,
+        interval: Duration,
+    ) -> Option<Duration> {
+        let last_passed = self.task_dirs.get(tasks_dir)?.get(name)?.last_passed?;
+        // A last run in the future (e.g. the clock changed) counts as just now.
+        let ago = Utc::now()
+            .signed_duration_since(last_passed)
+            .to_std()
+            .unwrap_or_default();
+        (ago < interval).then_some(ago)
+    }
+}
+
+/// Take an exclusive lock on the lock file next to the state file at `path`,
+/// waiting for any other run holding it. Released when the returned file is
+/// closed.
+fn lock(path: &Path) -> Result<File> {
+    let lock_path = path.with_extension("json.lock");
+    let lock_error = |e| E::Lock {
+        path: lock_path.clone(),
+        source: e,
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(lock_error)?;
+    loop {
+        // Safety: flock has no memory safety requirements.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(file);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(lock_error(e).into());
+        }
+    }
+}
+
+/// Short human-readable form of a duration, e.g. `3h` or `45s`.
+pub(crate) fn format_ago(ago: Duration) -> String {
+    let secs = ago.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86_399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86_400),
+    }
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum StateError {
+    /// Failed to read task state file '{path}'.
+    Read { path: PathBuf, source: io::Error },
+    /// Failed to parse task state file '{path}'.
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// Failed to write task state file '{path}'.
+    Write { path: PathBuf, source: io::Error },
+    /// Failed to lock task state lock file '{path}'.
+    Lock { path: PathBuf, source: io::Error },
+}
@@ -0,0 +1,155 @@
+//! Get superuser privileges for tasks, and keep them for the whole run.
+
+use std::{
+    io,
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use displaydoc::Display;
+use log::{debug, error};
+use thiserror::Error;
+
+use self::SudoError as E;
+
+/// How often to refresh the sudo timestamp, unless the up.toml sets
+/// `sudo_refresh_interval`. It expires after 5 minutes by default.
+pub(crate) const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Background thread that keeps the sudo timestamp fresh, so tasks don't get
+/// prompted for a password part way through the run. Stops when dropped.
+#[derive(Debug)]
+pub(crate) struct SudoKeepalive {
+    stop_tx: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    /// The most recent refresh failure, if any.
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+/// Prompt for superuser privileges with `sudo -v`, then keep them, refreshing
+/// every `refresh_interval`, until the returned keepalive is stopped or
+/// dropped.
+pub(crate) fn start(refresh_interval: Duration) -> Result<SudoKeepalive> {
+    debug!("Prompting for superuser privileges with 'sudo -v'");
+    let status = Command::new("sudo")
+        .arg("-v")
+        .status()
+        .map_err(|e| E::Run { source: e })?;
+    if !status.success() {
+        return Err(E::Prompt { status }.into());
+    }
+
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    let last_error = Arc::new(Mutex::new(None));
+    let thread_last_error = Arc::clone(&last_error);
+    let thread = thread::Builder::new()
+        .name("sudo-keepalive".to_owned())
+        .spawn(move || {
+            // Stop when told to, or when the keepalive is dropped.
+            while stop_rx.recv_timeout(refresh_interval) == Err(RecvTimeoutError::Timeout) {
+                let result = refresh();
+                let mut last_error = thread_last_error
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                match result {
+                    Ok(()) => *last_error = None,
+                    Err(e) => {
+                        let e = format!("{:#}", e);
+                        // Only report each failure once, not every refresh.
+                        if last_error.as_ref() != Some(&e) {
+                            error!(
+                                "{} Tasks that use sudo won't be started, running ones may hang \
+                                 waiting for a password.",
+                                e
+                            );
+                        }
+                        *last_error = Some(e);
+                    }
+                }
+            }
+        })
+        .map_err(|e| E::SpawnThread { source: e })?;
+
+    Ok(SudoKeepalive {
+        stop_tx: Some(stop_tx),
+        thread: Some(thread),
+        last_error,
+    })
+}
+
+/// Refresh the sudo timestamp without prompting.
+fn refresh() -> Result<()> {
+    debug!("Refreshing superuser privileges with 'sudo -n -v'");
+    let output = Command::new("sudo")
+        .args(["-n", "-v"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| E::Run { source: e })?;
+    if !output.status.success() {
+        return Err(E::Refresh {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+impl SudoKeepalive {
+    /// The refresh failure, if the most recent refresh failed. Tasks that
+    /// need sudo shouldn't be started while it is set, as they would prompt
+    /// for a password that nothing can enter.
+    pub(crate) fn expired(&self) -> Option<String> {
+        self.last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Stop refreshing the sudo timestamp. Returns the last refresh failure,
+    /// if the most recent refresh failed.
+    pub(crate) fn stop(mut self) -> Option<String> {
+        self.stop_thread();
+        self.last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    fn stop_thread(&mut self) {
+        // Dropping the sender wakes the thread up.
+        self.stop_tx.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Sudo keepalive thread panicked.");
+            }
+        }
+    }
+}
+
+impl Drop for SudoKeepalive {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+#[derive(Error, Debug, Display)]
+/// Errors thrown by this file.
+pub enum SudoError {
+    /// Failed to run sudo.
+    Run { source: io::Error },
+    /// Failed to start the sudo keepalive thread.
+    SpawnThread { source: io::Error },
+    /// Failed to get superuser privileges, 'sudo -v' failed with {status}.
+    Prompt { status: ExitStatus },
+    /// Failed to refresh superuser privileges, 'sudo -n -v' failed with {status}: {stderr}
+    Refresh { status: ExitStatus, stderr: String },
+    /// Not starting task '{name}' as superuser privileges expired: {error}
+    Expired { name: String, error: String },
+}
@@ -1,20 +1,34 @@
 use std::{
-    collections::HashMap,
-    fs,
-    io::Read,
+    collections::{HashMap, VecDeque},
+    ffi::OsStr,
+    fmt::{self, Debug, Write as _},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    os::{
+        fd::{FromRawFd, IntoRawFd},
+        unix::process::CommandExt,
+    },
     path::{Path, PathBuf},
     process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard, Once, PoisonError,
+    },
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
-use anyhow::{anyhow, bail, Result};
-use log::{debug, info, log, trace, Level};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, log, trace, warn, Level};
+use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::{
-    args::{GenerateGitConfig, LinkOptions, UpdateSelfOptions},
-    generate, tasks,
-    tasks::{defaults::DefaultsConfig, git::GitConfig, ResolveEnv, TasksError},
+    args::TaskOutput,
+    config::UpConfig,
+    tasks,
+    tasks::{constraints, plugin, run_lib, ResolveEnv, TaskError, TasksError},
 };
 
 #[derive(Debug)]
@@ -23,8 +37,10 @@ pub enum TaskStatus {
     New,
     /// Not yet ready to run as some requires still haven't finished.
     Blocked,
-    /// In progress.
-    Running(Child, Instant),
+    /// Ready to run, waiting for one of the running tasks to finish.
+    Queued,
+    /// In progress on a worker thread.
+    Running,
     /// Skipped.
     Skipped,
     /// Completed successfully.
@@ -38,18 +54,35 @@ pub struct Task {
     pub name: String,
     pub path: PathBuf,
     pub config: TaskConfig,
-    pub start_time: Instant,
     pub status: TaskStatus,
+    /// File the output of the task's commands is written to, if file logging
+    /// is enabled.
+    pub log_path: Option<PathBuf>,
+    /// How long the check command took, if it was run.
+    pub check_duration: Option<Duration>,
+    /// How long the run command or run library took, if it was run.
+    pub run_duration: Option<Duration>,
+    /// Exit code of the run command, if it exited normally.
+    pub exit_code: Option<i32>,
+    /// Number of times the run command or run library has been started.
+    pub attempts: u32,
+    /// The check's `stdout_regex`, compiled when the task is loaded.
+    check_regex: Option<Regex>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct TaskConfig {
-    /// Task name, defaults to file name (minus extension) if unset.
+    /// Task name, defaults to file name (minus extension) or directory name if
+    /// unset. Inline tasks in the up.toml default to their `[tasks.<name>]`
+    /// key, and `[[tasks]]` entries must set it.
     pub name: Option<String>,
-    /// Set of Constraints that will cause the task to be run.
+    /// Set of Constraints that will cause the task to be run, e.g. `os = "linux"` or
+    /// `executable = "brew"`. Tasks whose constraints don't match are skipped. See
+    /// [`constraints`](crate::tasks::constraints) for the full list.
     pub constraints: Option<HashMap<String, String>>,
-    /// Tasks that must have been executed beforehand.
+    /// Tasks that must have been executed beforehand. This task won't start until
+    /// all of them have passed or been skipped, and will be skipped if any of them fail.
     pub requires: Option<Vec<String>>,
     /// Whether to run this by default, or only if required.
     pub auto_run: Option<bool>,
@@ -57,14 +90,68 @@ pub struct TaskConfig {
     /// `run_cmd` + `check_cmd`.
     pub run_lib: Option<String>,
     /// Check command: only run the `run_cmd` if this command returns a non-zero
-    /// exit code.
+    /// exit code (or fails the `check` conditions if set).
     pub check_cmd: Option<Vec<String>>,
+    /// How to decide whether the `check_cmd` passed, and so the task should be
+    /// skipped.
+    pub check: Option<CheckConfig>,
     /// Run command: command to run to perform the update.
     pub run_cmd: Option<Vec<String>>,
+    /// Directory to run the check and run commands in. Relative paths are
+    /// relative to the directory containing the task file, which is also the
+    /// default.
+    pub cwd: Option<String>,
     /// Set of data provided to the Run library.
     pub data: Option<toml::Value>,
     /// Description of the task.
     pub description: Option<String>,
+    /// Environment variables to set for this task, on top of the `env` in the
+    /// up.toml. Values can refer to other env vars, e.g.
+    /// `PATH = "$CARGO_HOME/bin:$PATH"`.
+    pub env: Option<HashMap<String, String>>,
+    /// Environment variables to inherit from the environment up is run in, on
+    /// top of the `inherit_env` in the up.toml.
+    pub inherit_env: Option<Vec<String>>,
+    /// Timeout in seconds for each of the check and run commands. If a command
+    /// runs for longer it is terminated and the task fails. Defaults to the
+    /// `timeout` set in the up.toml, or no timeout.
+    pub timeout: Option<u64>,
+    /// Number of times to retry the run command (or run library) if it fails.
+    /// Default is 0 (no retries).
+    pub retries: Option<u32>,
+    /// Seconds to wait before retrying a failed run command. Default is 0.
+    pub retry_delay: Option<u64>,
+    /// Double the `retry_delay` after each failed attempt.
+    pub retry_backoff: Option<bool>,
+    /// Stop the run if this task fails: don't start any more tasks and
+    /// terminate the ones that are running, as with `up run --fail-fast`.
+    /// Ignored with `--keep-going`.
+    pub critical: Option<bool>,
+    /// Whether the task needs superuser privileges. If any task that will be
+    /// run needs them, you are prompted for your password once at the start of
+    /// the run. Defaults to the `needs_sudo` set in the up.toml.
+    pub needs_sudo: Option<bool>,
+    /// Seconds to wait after the task last passed before running it again, e.g.
+    /// `86400` to run it at most once a day. Until then the task is skipped.
+    /// Ignored with `up run --force`.
+    pub run_interval: Option<u64>,
+}
+
+/// How to judge the output of a task's `check_cmd`. The check passes (and the
+/// task is skipped) if every condition that is set holds.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckConfig {
+    /// Exit codes that count as the check passing. Default is `[0]`.
+    pub skip_exit_codes: Option<Vec<i32>>,
+    /// Regex that stdout must match, e.g. `"^rustc 1\\.5"`.
+    pub stdout_regex: Option<String>,
+    /// String that stdout must equal (ignoring leading and trailing whitespace),
+    /// e.g. a version. Env vars are expanded.
+    pub stdout_equals: Option<String>,
+    /// Invert the result, so the task is run when the check passes and skipped
+    /// when it fails.
+    pub invert: Option<bool>,
 }
 
 /// Shell commands we run.
@@ -74,135 +161,332 @@ pub enum CommandType {
     Check,
     /// run_cmd field in the toml.
     Run,
+    /// `up-<name>` plugin for the `run_lib` field in the toml.
+    Plugin(plugin::Mode),
+}
+
+impl fmt::Display for CommandType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Check => write!(f, "check_cmd"),
+            Self::Run => write!(f, "run_cmd"),
+            Self::Plugin(mode) => write!(f, "plugin {}", mode),
+        }
+    }
+}
+
+/// How long to wait for a command to exit after sending it SIGTERM before
+/// sending it SIGKILL.
+pub(crate) const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Number of lines of stderr to include in the error when a run command fails.
+const STDERR_TAIL_LINES: usize = 10;
+
+/// The last lines a command wrote to stderr, shared with the thread reading
+/// them so they can be shown even if the command never exits.
+#[derive(Debug, Default, Clone)]
+struct StderrTail(Arc<Mutex<VecDeque<String>>>);
+
+impl StderrTail {
+    fn push(&self, line: String) {
+        let mut tail = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        tail.push_back(line);
+        if tail.len() > STDERR_TAIL_LINES {
+            tail.pop_front();
+        }
+    }
+
+    /// The lines read so far.
+    fn lines(&self) -> VecDeque<String> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Threads streaming a running command's output.
+#[derive(Debug, Default)]
+pub struct OutputReaders {
+    stdout: Option<JoinHandle<io::Result<()>>>,
+    stderr: Option<JoinHandle<io::Result<()>>>,
+    stderr_tail: StderrTail,
+}
+
+impl OutputReaders {
+    /// Wait for the rest of the output to be read, returning the last lines of
+    /// stderr.
+    fn join(self) -> Result<VecDeque<String>> {
+        if let Some(stdout) = self.stdout {
+            stdout
+                .join()
+                .map_err(|_| anyhow!("Failed to read task stdout"))??;
+        }
+        if let Some(stderr) = self.stderr {
+            stderr
+                .join()
+                .map_err(|_| anyhow!("Failed to read task stderr"))??;
+        }
+        Ok(self.stderr_tail.lines())
+    }
+}
+
+/// Tracks the commands tasks are running, so they can all be terminated if the
+/// run is stopped early (e.g. after a failure with `--fail-fast`).
+#[derive(Debug, Default)]
+pub struct Abort {
+    state: Mutex<AbortState>,
+    /// Notified when the run is stopped.
+    stopped: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct AbortState {
+    /// Whether the run has been stopped.
+    aborted: bool,
+    /// The running commands, by task name.
+    processes: HashMap<String, Process>,
+}
+
+/// A running command.
+#[derive(Debug, Clone, Copy)]
+struct Process {
+    pid: libc::pid_t,
+    /// Whether the command leads its own process group, so anything it started
+    /// can be signalled along with it. Commands using the terminal directly stay
+    /// in our group so they can read from it.
+    own_group: bool,
+}
+
+impl Process {
+    /// Send `signal` to the command (and its process group if it has one),
+    /// ignoring errors (e.g. if it has already exited).
+    fn signal(self, signal: libc::c_int) {
+        // Safety: kill and killpg have no memory safety requirements.
+        unsafe {
+            if self.own_group {
+                libc::killpg(self.pid, signal);
+            } else {
+                libc::kill(self.pid, signal);
+            }
+        }
+    }
+}
+
+impl Abort {
+    /// Stop the run, sending `signal` to every running command (and anything
+    /// it started). Commands started after this are sent SIGTERM as soon as
+    /// they start.
+    pub fn abort(&self, signal: libc::c_int) {
+        let mut state = self.lock();
+        state.aborted = true;
+        for (name, process) in &state.processes {
+            debug!(
+                "Sending signal {} to task '{}' process {:?}",
+                signal, name, process
+            );
+            process.signal(signal);
+        }
+        drop(state);
+        self.stopped.notify_all();
+    }
+
+    /// Whether the run has been stopped.
+    pub fn is_aborted(&self) -> bool {
+        self.lock().aborted
+    }
+
+    /// Wait for `timeout`, returning early if the run is stopped. Returns
+    /// whether it was stopped.
+    fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.stopped
+            .wait_timeout_while(self.lock(), timeout, |state| !state.aborted)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+            .aborted
+    }
+
+    /// Track the command task `name` is running.
+    fn register(&self, name: &str, process: Process) {
+        let mut state = self.lock();
+        if state.aborted {
+            debug!("Sending SIGTERM to task '{}' process {:?}", name, process);
+            process.signal(libc::SIGTERM);
+        }
+        state.processes.insert(name.to_owned(), process);
+    }
+
+    /// Stop tracking task `name`'s command once it has exited.
+    fn unregister(&self, name: &str) {
+        self.lock().processes.remove(name);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AbortState> {
+        // The state is always valid, even if a thread panicked holding the lock.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
+/// Script in a task directory that is the task's check command.
+const DIR_CHECK_SCRIPT: &str = "check";
+/// Script in a task directory that is the task's run command.
+const DIR_UPDATE_SCRIPT: &str = "update";
+/// Optional config files in a task directory, the first that exists is used.
+const DIR_CONFIG_FILES: [&str; 4] = ["task.toml", "task.yaml", "task.yml", "task.json"];
+
 impl Task {
+    /// Read a task from a task file, or from a task directory (see
+    /// [`is_task_dir()`](Self::is_task_dir)).
     pub fn from(path: &Path) -> Result<Self> {
-        let start_time = Instant::now();
-        let s = fs::read_to_string(&path).map_err(|e| TasksError::ReadFile {
-            path: path.to_owned(),
-            source: e,
-        })?;
-        trace!("Task '{:?}' contents: <<<{}>>>", &path, &s);
-        let config = toml::from_str::<TaskConfig>(&s).map_err(|e| TasksError::InvalidToml {
-            path: path.to_owned(),
-            source: e,
-        })?;
+        let is_dir = path.is_dir();
+        let config = if is_dir {
+            read_dir_config(path)?
+        } else {
+            read_config(path)?
+        };
         let name = match &config.name {
             Some(n) => n.clone(),
-            None => path
-                .file_stem()
-                .ok_or_else(|| anyhow!("Task had no path."))?
-                .to_str()
-                .ok_or(TasksError::None {})?
-                .to_owned(),
+            None => if is_dir {
+                path.file_name()
+            } else {
+                path.file_stem()
+            }
+            .ok_or_else(|| anyhow!("Task had no path."))?
+            .to_str()
+            .ok_or(TasksError::None {})?
+            .to_owned(),
+        };
+        Self::new(name, path, config)
+    }
+
+    /// Create a task from an already parsed config, e.g. a task defined inline
+    /// in the up.toml. `path` is the file it was defined in.
+    pub fn new(name: String, path: &Path, config: TaskConfig) -> Result<Self> {
+        if config.check.is_some() && config.check_cmd.is_none() {
+            return Err(TaskError::CheckWithoutCheckCmd { name }.into());
+        }
+        if let Some(constraints) = &config.constraints {
+            constraints::validate(&name, constraints)?;
+        }
+        let check_regex = match config.check.as_ref().and_then(|c| c.stdout_regex.as_ref()) {
+            Some(regex) => Some(Regex::new(regex).map_err(|e| TaskError::InvalidCheckRegex {
+                name: name.clone(),
+                regex: regex.clone(),
+                source: e,
+            })?),
+            None => None,
         };
-        let status = TaskStatus::New;
         let task = Self {
             name,
             path: path.to_owned(),
             config,
-            status,
-            start_time,
+            status: TaskStatus::New,
+            log_path: None,
+            check_duration: None,
+            run_duration: None,
+            exit_code: None,
+            attempts: 0,
+            check_regex,
         };
         debug!("Task '{}': {:?}", &task.name, task);
         Ok(task)
     }
 
-    pub fn try_start<F>(&mut self, env_fn: F, env: &HashMap<String, String>) -> Result<()>
-    where
-        F: Fn(&str) -> Result<String>,
-    {
-        // TODO(gib): actually check whether we're blocked.
-
-        self.status = TaskStatus::Blocked;
-        self.start(env_fn, env)
+    /// Whether `dir` is a task directory, i.e. it contains a `check` or
+    /// `update` script, or a `task.toml` (or `.yaml`, `.yml`, `.json`) config
+    /// file. The scripts are the task's check and run commands, and the config
+    /// file can set anything else.
+    pub(crate) fn is_task_dir(dir: &Path) -> bool {
+        [DIR_CHECK_SCRIPT, DIR_UPDATE_SCRIPT]
+            .iter()
+            .chain(&DIR_CONFIG_FILES)
+            .any(|file| dir.join(file).exists())
     }
 
+    /// Run the task to completion, blocking until it has finished, and set its
+    /// final status. In dry-run mode only the check command is run, and the task
+    /// is marked as passed if it would have been run.
+    ///
+    /// If `passthrough` is set the run command's output goes straight to the
+    /// terminal, rather than being streamed through the logger (it is still
+    /// copied to the task log file, if there is one, unless the output is a
+    /// terminal). The task's commands are terminated if the run is stopped with
+    /// `abort`.
     // TODO(gib): Test for this (using basic config).
-    pub fn start<F>(&mut self, env_fn: F, env: &HashMap<String, String>) -> Result<()>
-    where
-        F: Fn(&str) -> Result<String>,
-    {
-        info!("Running task '{}'", &self.name);
+    pub fn run(
+        &mut self,
+        global_env: &HashMap<String, String>,
+        config: &UpConfig,
+        passthrough: bool,
+        abort: &Abort,
+    ) {
+        self.status = TaskStatus::Running;
+        if let Err(e) = self.try_run(global_env, config, passthrough, abort) {
+            self.status = TaskStatus::Failed(e);
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn try_run(
+        &mut self,
+        global_env: &HashMap<String, String>,
+        config: &UpConfig,
+        passthrough: bool,
+        abort: &Abort,
+    ) -> Result<()> {
+        let dry_run = config.dry_run;
+        let env = self.env(global_env)?;
+        let env_fn = tasks::env_fn(&env);
+
+        if let Some(failed) = self.failed_constraint(&env)? {
+            info!(
+                "Skipping task '{}' as constraint {} didn't match.",
+                &self.name, failed
+            );
+            self.status = TaskStatus::Skipped;
+            return Ok(());
+        }
+
+        if dry_run {
+            info!("Checking task '{}'", &self.name);
+        } else {
+            info!("Running task '{}'", &self.name);
+        }
         self.status = TaskStatus::Passed;
 
-        if let Some(lib) = &self.config.run_lib {
-            let run_lib_result = match lib.as_str() {
-                "link" => {
-                    let mut data = self
-                        .config
-                        .data
-                        .as_ref()
-                        .ok_or_else(|| anyhow!("Task '{}' data had no value.", &self.name))?
-                        .clone()
-                        .try_into::<LinkOptions>()?;
-                    data.resolve_env(env_fn)?;
-                    tasks::link::run(data)
-                }
-                "git" => {
-                    let mut data = self
-                        .config
-                        .data
-                        .as_ref()
-                        .ok_or_else(|| anyhow!("Task '{}' data had no value.", &self.name))?
-                        .clone()
-                        .try_into::<Vec<GitConfig>>()?;
-                    data.resolve_env(env_fn)?;
-                    tasks::git::run(data)
-                }
-                "generate_git" => {
-                    let mut data = self
-                        .config
-                        .data
-                        .as_ref()
-                        .ok_or_else(|| anyhow!("Task '{}' data had no value.", &self.name))?
-                        .clone()
-                        .try_into::<Vec<GenerateGitConfig>>()?;
-                    data.resolve_env(env_fn)?;
-                    generate::git::run(&data)
-                }
-                "defaults" => {
-                    let mut data = self
-                        .config
-                        .data
-                        .as_ref()
-                        .ok_or_else(|| anyhow!("Task '{}' data had no value.", &self.name))?
-                        .clone()
-                        .try_into::<DefaultsConfig>()?;
-                    data.resolve_env(env_fn)?;
-                    tasks::defaults::run(data)
-                }
-                "self" => {
-                    let options = if let Some(raw_data) = self.config.data.as_ref() {
-                        let mut raw_opts = raw_data.clone().try_into::<UpdateSelfOptions>()?;
-                        raw_opts.resolve_env(env_fn)?;
-                        raw_opts
-                    } else {
-                        UpdateSelfOptions::default()
-                    };
-                    tasks::update_self::run(&options)
+        self.log_path = config
+            .task_log_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.log", self.name.replace('/', "_"))));
+
+        if let Some(lib) = self.config.run_lib.clone() {
+            if !run_lib::is_registered(&lib) {
+                let plugin_dir = plugin::plugin_dir(config, &env_fn)?;
+                if let Some(plugin) = plugin::find(&lib, plugin_dir.as_deref(), &env) {
+                    let cwd = self.cwd(&env_fn)?;
+                    return self.run_plugin(&plugin, &cwd, &env, &env_fn, dry_run, abort);
                 }
-                _ => Err(anyhow!("This run_lib is invalid or not yet implemented.")),
-            };
-            if let Err(e) = run_lib_result {
-                self.status = TaskStatus::Failed(e);
-            } else {
-                self.status = TaskStatus::Passed;
             }
-            return Ok(());
+            let run_lib_start = Instant::now();
+            let run_lib_result = run_lib::run_task(&lib, self, &env_fn, dry_run, abort);
+            self.run_duration = Some(run_lib_start.elapsed());
+            return run_lib_result;
         }
 
+        let cwd = self.cwd(&env_fn)?;
+
         if let Some(mut cmd) = self.config.check_cmd.clone() {
             debug!("Running '{}' check command.", &self.name);
             for s in &mut cmd {
                 *s = env_fn(s)?;
             }
-            let check_output = self.run_check_cmd(&cmd, env)?;
-            // TODO(gib): Allow choosing how to validate check_cmd output (stdout, zero exit
-            // code, non-zero exit code).
-            if check_output.status.success() {
-                debug!("Skipping task '{}' as check command passed.", &self.name);
+            let check_output = self.run_check_cmd(&cmd, &cwd, &env, abort)?;
+            if self.check_passed(&check_output, &env_fn)? {
+                log!(
+                    if dry_run { Level::Info } else { Level::Debug },
+                    "Skipping task '{}' as check command passed.",
+                    &self.name
+                );
                 self.status = TaskStatus::Skipped;
                 return Ok(());
             }
@@ -220,8 +504,20 @@ impl Task {
             for s in &mut cmd {
                 *s = env_fn(s)?;
             }
-            let (child, start_time) = Self::start_command(&cmd, env)?;
-            self.status = TaskStatus::Running(child, start_time);
+            if dry_run {
+                info!(
+                    "Task '{}' would run: {:?} (in '{}')",
+                    &self.name,
+                    cmd,
+                    cwd.display()
+                );
+                self.status = TaskStatus::Passed;
+                return Ok(());
+            }
+            self.with_retries(abort, |task| {
+                task.run_cmd(&cmd, &cwd, &env, passthrough, config.task_output, abort)
+            })?;
+            self.status = TaskStatus::Passed;
             return Ok(());
         }
 
@@ -230,59 +526,287 @@ impl Task {
         });
     }
 
-    /// If command has completed set output state.
-    pub fn try_finish(&mut self) -> Result<()> {
-        let (child, start_time) = match &mut self.status {
-            TaskStatus::Running(child, start_time) => (child, start_time),
-            _ => bail!(anyhow!("Can't finish non-running task.")),
+    /// The first of the task's constraints that doesn't match, if any.
+    fn failed_constraint(&self, env: &HashMap<String, String>) -> Result<Option<String>> {
+        self.config
+            .constraints
+            .as_ref()
+            .map_or(Ok(None), |constraints| {
+                constraints::failed_constraint(&self.name, constraints, tasks::env_fn(env), env)
+            })
+    }
+
+    /// Whether the task will need superuser privileges if it is run, i.e. it
+    /// sets `needs_sudo` and its constraints match. If the constraints can't be
+    /// checked we assume they match, the task will fail when it is run.
+    pub(crate) fn needs_sudo(&self, global_env: &HashMap<String, String>) -> bool {
+        self.config.needs_sudo.unwrap_or(false)
+            && self
+                .env(global_env)
+                .and_then(|env| self.failed_constraint(&env))
+                .map_or(true, |failed| failed.is_none())
+    }
+
+    /// The env for this task, the global env with the task's `inherit_env` and
+    /// `env` added.
+    fn env(&self, global_env: &HashMap<String, String>) -> Result<HashMap<String, String>> {
+        if self.config.inherit_env.is_none() && self.config.env.is_none() {
+            return Ok(global_env.clone());
+        }
+        crate::env::extend_env(
+            global_env.clone(),
+            self.config.inherit_env.as_ref(),
+            self.config.env.as_ref(),
+        )
+        .with_context(|| format!("Failed to resolve env for task '{}'", self.name))
+    }
+
+    /// Directory to run the task's commands in.
+    fn cwd<F>(&self, env_fn: F) -> Result<PathBuf>
+    where
+        F: Fn(&str) -> Result<String>,
+    {
+        let task_dir = if self.path.is_dir() {
+            &self.path
+        } else {
+            self.path.parent().unwrap_or_else(|| Path::new("/"))
+        };
+        let cwd = match &self.config.cwd {
+            Some(cwd) => task_dir.join(env_fn(cwd)?),
+            None => task_dir.to_owned(),
         };
+        if !cwd.is_dir() {
+            bail!(TasksError::MissingCwd {
+                name: self.name.clone(),
+                path: cwd,
+            });
+        }
+        Ok(cwd)
+    }
 
-        if let Some(status) = child.try_wait()? {
-            debug!("Task '{}' complete.", &self.name);
-            let elapsed_time = start_time.elapsed();
-
-            let mut stdout = String::new();
-            child
-                .stdout
-                .as_mut()
-                .ok_or_else(|| anyhow!("Missing stdout"))?
-                .read_to_string(&mut stdout)?;
-
-            let mut stderr = String::new();
-            child
-                .stderr
-                .as_mut()
-                .ok_or_else(|| anyhow!("Missing stderr"))?
-                .read_to_string(&mut stderr)?;
-
-            self.log_command_output(CommandType::Run, status, &stdout, &stderr, elapsed_time);
-            if status.success() {
-                self.status = TaskStatus::Passed;
-            } else {
-                // TODO(gib): Error should include an easy way to see the task logs.
-                self.status = TaskStatus::Failed(anyhow!("Task {} failed.", self.name));
+    /// Parse the task's `data` for its `run_lib`, expanding env vars.
+    pub(crate) fn data<T, F>(&self, env_fn: F) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + ResolveEnv,
+        F: Fn(&str) -> Result<String>,
+    {
+        self.config
+            .data
+            .as_ref()
+            .map(|raw_data| {
+                let mut data = raw_data.clone().try_into::<T>()?;
+                data.resolve_env(&env_fn)?;
+                Ok(data)
+            })
+            .transpose()
+    }
+
+    /// Run the `run_lib` with its parsed `data`, retrying if it fails, or in
+    /// dry-run mode log what would have been run.
+    pub(crate) fn run_lib<T, R>(
+        &mut self,
+        lib: &str,
+        data: T,
+        dry_run: bool,
+        abort: &Abort,
+        run: R,
+    ) -> Result<()>
+    where
+        T: Debug + Clone,
+        R: Fn(T) -> Result<()>,
+    {
+        if dry_run {
+            info!(
+                "Task '{}' would run run_lib '{}' with data: {:?}",
+                &self.name, lib, data
+            );
+            return Ok(());
+        }
+        self.with_retries(abort, |_| run(data.clone()))
+    }
+
+    /// Call `attempt` until it succeeds or the task runs out of retries,
+    /// waiting for the retry delay in between. Doesn't retry once the run has
+    /// been stopped, including while waiting to retry.
+    fn with_retries<F>(&mut self, abort: &Abort, mut attempt: F) -> Result<()>
+    where
+        F: FnMut(&mut Self) -> Result<()>,
+    {
+        loop {
+            self.attempts += 1;
+            match attempt(self) {
+                Ok(()) => return Ok(()),
+                Err(e) if abort.is_aborted() => return Err(self.with_attempts(e)),
+                Err(e) => match self.retry_delay(&e) {
+                    Some(delay) if !abort.wait_timeout(delay) => {
+                        info!("Retrying task '{}'", &self.name);
+                    }
+                    _ => return Err(self.with_attempts(e)),
+                },
             }
-        } else {
-            // Still running.
-            // trace!("Task '{}' still in progress.", &self.name);
         }
+    }
 
-        Ok(())
+    /// How long to wait before retrying after attempt `self.attempts` failed
+    /// with `e`, or `None` if there are no retries left.
+    fn retry_delay(&self, e: &anyhow::Error) -> Option<Duration> {
+        let retries = self.config.retries.unwrap_or(0);
+        if self.attempts > retries {
+            return None;
+        }
+        let mut delay = Duration::from_secs(self.config.retry_delay.unwrap_or(0));
+        if self.config.retry_backoff.unwrap_or(false) {
+            delay = delay.saturating_mul(2_u32.saturating_pow(self.attempts - 1));
+        }
+        warn!(
+            "Task '{}' failed (attempt {} of {}), retrying in {:?}: {}",
+            &self.name,
+            self.attempts,
+            retries + 1,
+            delay,
+            e
+        );
+        Some(delay)
     }
 
-    pub fn run_check_cmd(&self, cmd: &[String], env: &HashMap<String, String>) -> Result<Output> {
-        let mut command = Self::get_command(cmd, env)?;
+    /// Note how many attempts were made in the error for a task that was retried.
+    fn with_attempts(&self, e: anyhow::Error) -> anyhow::Error {
+        if self.attempts > 1 {
+            e.context(format!(
+                "Task '{}' failed after {} attempts.",
+                &self.name, self.attempts
+            ))
+        } else {
+            e
+        }
+    }
 
-        let now = Instant::now();
-        let output = command.output().map_err(|e| TasksError::CheckCmdFailed {
+    /// Run the run command, streaming its output, and wait for it to finish.
+    /// Terminates the command if it runs for longer than the task timeout.
+    fn run_cmd(
+        &mut self,
+        cmd: &[String],
+        cwd: &Path,
+        env: &HashMap<String, String>,
+        passthrough: bool,
+        task_output: TaskOutput,
+        abort: &Abort,
+    ) -> Result<()> {
+        self.append_to_log(&format!(
+            "==> {} (attempt {}): {:?}\n",
+            CommandType::Run,
+            self.attempts,
+            cmd
+        ));
+        let mut command = self.get_command(cmd, cwd, env)?;
+        if !passthrough {
+            // Other tasks are using the terminal, and anything waiting for input
+            // (e.g. a sudo password prompt) would hang. In its own process group
+            // so on timeout or abort we can terminate anything it started. A
+            // command using the terminal has to stay in our group, as reading
+            // from it in a background group would stop it with SIGTTIN.
+            command.stdin(Stdio::null()).process_group(0);
+        }
+        // Passed through output keeps our stdout and stderr, so a command writing
+        // to a terminal can tell (e.g. to show colours or progress bars). If
+        // they aren't terminals the output is piped instead, so it can be copied
+        // into the task log file and then written unchanged to ours.
+        let inherit_output = passthrough
+            && (self.log_path.is_none()
+                || io::stdout().is_terminal()
+                || io::stderr().is_terminal());
+        if inherit_output {
+            self.append_to_log("==> Output went straight to the terminal, so isn't logged here.\n");
+        } else {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let start_time = Instant::now();
+        let mut child = command.spawn().map_err(|e| TasksError::CmdSpawn {
             name: self.name.clone(),
+            command: CommandType::Run,
             cmd: cmd.into(),
+            cwd: cwd.to_owned(),
             source: e,
         })?;
+        let output_readers = self.stream_output(&mut child, task_output, passthrough);
+        let status = self.wait(
+            child,
+            CommandType::Run,
+            !passthrough,
+            &output_readers.stderr_tail,
+            abort,
+        );
+        let elapsed_time = start_time.elapsed();
+        self.run_duration = Some(elapsed_time);
+        // If the command timed out or was terminated don't wait for the output
+        // readers, anything the command started in the background could still be
+        // holding its stdout or stderr open.
+        let status = status?;
+        debug!("Task '{}' complete.", &self.name);
+        self.exit_code = status.code();
+
+        // Wait for the rest of the output to be logged.
+        let stderr_tail = output_readers.join()?;
 
+        // Output has already been streamed.
+        self.log_command_output(CommandType::Run, status, "", "", elapsed_time);
+        self.append_to_log(&format!(
+            "==> {} finished with {}\n",
+            CommandType::Run,
+            status
+        ));
+        if !status.success() {
+            bail!(TasksError::CmdFailed {
+                name: self.name.clone(),
+                command: CommandType::Run,
+                status,
+                details: self.failure_info(&stderr_tail),
+            });
+        }
+        Ok(())
+    }
+
+    /// Run the check command, terminating it if it runs for longer than the
+    /// task timeout.
+    pub fn run_check_cmd(
+        &mut self,
+        cmd: &[String],
+        cwd: &Path,
+        env: &HashMap<String, String>,
+        abort: &Abort,
+    ) -> Result<Output> {
+        let mut command = self.get_command(cmd, cwd, env)?;
+
+        let now = Instant::now();
+        // Like the run command, nothing can answer a prompt.
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| TasksError::CheckCmdFailed {
+                name: self.name.clone(),
+                cmd: cmd.into(),
+                source: e,
+            })?;
+        let output = self.wait_for_output(child, CommandType::Check, abort);
+        // Recorded even if the command timed out or was terminated.
         let elapsed_time = now.elapsed();
+        self.check_duration = Some(elapsed_time);
+        let output = output?;
+
         let stdout = String::from_utf8_lossy(&output.stdout);
         let stderr = String::from_utf8_lossy(&output.stderr);
+        self.append_to_log(&format!(
+            "==> {}: {:?}\n{}{}==> {} finished with {}\n",
+            CommandType::Check,
+            cmd,
+            stdout,
+            stderr,
+            CommandType::Check,
+            output.status
+        ));
 
         self.log_command_output(
             CommandType::Check,
@@ -294,27 +818,348 @@ impl Task {
         Ok(output)
     }
 
-    pub fn start_command(
-        cmd: &[String],
+    /// Wait for a command whose stdout and stderr are piped to exit, collecting
+    /// its output.
+    fn wait_for_output(
+        &self,
+        mut child: Child,
+        command: CommandType,
+        abort: &Abort,
+    ) -> Result<Output> {
+        // Read output in the background so the command can't block on a full pipe.
+        let stderr_tail = StderrTail::default();
+        let stdout_reader = read_in_background(child.stdout.take(), None);
+        let stderr_reader = read_in_background(child.stderr.take(), Some(stderr_tail.clone()));
+
+        let status = self.wait(child, command, true, &stderr_tail, abort)?;
+        Ok(Output {
+            status,
+            stdout: stdout_reader
+                .join()
+                .map_err(|_| anyhow!("Failed to read stdout"))??,
+            stderr: stderr_reader
+                .join()
+                .map_err(|_| anyhow!("Failed to read stderr"))??,
+        })
+    }
+
+    /// Run the task's `run_lib` plugin (see [`plugin`]), first in check mode
+    /// and then, if the check says anything would change, in run mode.
+    fn run_plugin<F>(
+        &mut self,
+        plugin: &Path,
+        cwd: &Path,
+        env: &HashMap<String, String>,
+        env_fn: F,
+        dry_run: bool,
+        abort: &Abort,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> Result<String>,
+    {
+        let data = self
+            .data::<toml::Value, _>(env_fn)?
+            .unwrap_or_else(|| toml::Value::Table(toml::value::Table::new()));
+        let stdin = serde_json::to_vec(&data)?;
+
+        debug!("Running '{}' plugin check.", &self.name);
+        let check = self.run_plugin_cmd(plugin, plugin::Mode::Check, &stdin, cwd, env, abort)?;
+        if check.status == plugin::Status::Unchanged {
+            log!(
+                if dry_run { Level::Info } else { Level::Debug },
+                "Skipping task '{}' as plugin check found nothing to change.",
+                &self.name
+            );
+            self.status = TaskStatus::Skipped;
+            return Ok(());
+        }
+        if dry_run {
+            info!(
+                "Task '{}' would run plugin '{}' with data: {}",
+                &self.name,
+                plugin.display(),
+                String::from_utf8_lossy(&stdin)
+            );
+            return Ok(());
+        }
+
+        debug!("Running '{}' plugin.", &self.name);
+        let mut changed = true;
+        self.with_retries(abort, |task| {
+            let result = task.run_plugin_cmd(plugin, plugin::Mode::Run, &stdin, cwd, env, abort)?;
+            changed = result.status == plugin::Status::Changed;
+            Ok(())
+        })?;
+        if !changed {
+            info!("Task '{}' plugin made no changes.", &self.name);
+            self.status = TaskStatus::Skipped;
+        }
+        Ok(())
+    }
+
+    /// Run the plugin in `mode` with `stdin`, and return what it reported.
+    /// Errors if it reported a failure.
+    fn run_plugin_cmd(
+        &mut self,
+        plugin: &Path,
+        mode: plugin::Mode,
+        stdin: &[u8],
+        cwd: &Path,
         env: &HashMap<String, String>,
-    ) -> Result<(Child, Instant)> {
-        let command = Self::get_command(cmd, env);
+        abort: &Abort,
+    ) -> Result<plugin::PluginResult> {
+        let cmd = [
+            plugin.to_str().ok_or(TasksError::None {})?.to_owned(),
+            mode.to_string(),
+        ];
+        let mut command = self.get_command(&cmd, cwd, env)?;
+
         let now = Instant::now();
-        let child = command?
+        let mut child = command
+            .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .spawn()?;
-        Ok((child, now))
+            .process_group(0)
+            .spawn()
+            .map_err(|e| TasksError::PluginSpawn {
+                name: self.name.clone(),
+                plugin: plugin.to_owned(),
+                source: e,
+            })?;
+        // Write in the background so a plugin that doesn't read all its input
+        // can't block us. It gets a broken pipe error if the plugin exits first.
+        let stdin_writer = child.stdin.take().map(|mut pipe| {
+            let stdin = stdin.to_vec();
+            thread::spawn(move || pipe.write_all(&stdin))
+        });
+        let output = self.wait_for_output(child, CommandType::Plugin(mode), abort);
+        let elapsed_time = now.elapsed();
+        match mode {
+            plugin::Mode::Check => self.check_duration = Some(elapsed_time),
+            plugin::Mode::Run => self.run_duration = Some(elapsed_time),
+        }
+        let output = output?;
+        if let Some(stdin_writer) = stdin_writer {
+            if let Ok(Err(e)) = stdin_writer.join() {
+                debug!(
+                    "Task '{}' plugin didn't read all its input: {}",
+                    &self.name, e
+                );
+            }
+        }
+        if matches!(mode, plugin::Mode::Run) {
+            self.exit_code = output.status.code();
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let command_type = CommandType::Plugin(mode);
+        self.append_to_log(&format!(
+            "==> {}: {:?}\n{}{}==> {} finished with {}\n",
+            command_type, cmd, stdout, stderr, command_type, output.status
+        ));
+        self.log_command_output(command_type, output.status, &stdout, &stderr, elapsed_time);
+
+        let result = plugin::PluginResult::from_output(&output);
+        if result.status == plugin::Status::Failed {
+            let mut details = String::new();
+            if let Some(message) = &result.message {
+                let _ = write!(details, "\n  {}", message);
+            }
+            let mut stderr_tail: VecDeque<String> = stderr
+                .lines()
+                .rev()
+                .take(STDERR_TAIL_LINES)
+                .map(ToOwned::to_owned)
+                .collect();
+            stderr_tail.make_contiguous().reverse();
+            details.push_str(&self.failure_info(&stderr_tail));
+            bail!(TasksError::PluginFailed {
+                name: self.name.clone(),
+                plugin: plugin.to_owned(),
+                mode,
+                status: output.status,
+                details,
+            });
+        }
+        if let Some(message) = &result.message {
+            info!("Task '{}' plugin {}: {}", &self.name, mode, message);
+        }
+        Ok(result)
+    }
+
+    /// Whether the check command output means the task should be skipped.
+    fn check_passed<F>(&self, output: &Output, env_fn: F) -> Result<bool>
+    where
+        F: Fn(&str) -> Result<String>,
+    {
+        let check = match &self.config.check {
+            Some(check) => check,
+            None => return Ok(output.status.success()),
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        let mut passed = match (&check.skip_exit_codes, output.status.code()) {
+            (Some(codes), Some(code)) => codes.contains(&code),
+            (None, _) => output.status.success(),
+            // Killed by a signal.
+            (Some(_), None) => false,
+        };
+        if let Some(regex) = &self.check_regex {
+            passed = passed && regex.is_match(&stdout);
+        }
+        if let Some(expected) = &check.stdout_equals {
+            passed = passed && stdout.trim() == env_fn(expected)?.trim();
+        }
+        trace!(
+            "Task '{}' check passed: {}, inverted: {:?}",
+            &self.name,
+            passed,
+            check.invert
+        );
+        Ok(passed != check.invert.unwrap_or(false))
+    }
+
+    /// Wait for a command to exit. If it runs for longer than the task timeout,
+    /// or is terminated because the run was stopped, an error is returned
+    /// including the end of what it wrote to stderr so far.
+    /// `own_group` is whether the command was started in its own process group.
+    fn wait(
+        &self,
+        mut child: Child,
+        command: CommandType,
+        own_group: bool,
+        stderr_tail: &StderrTail,
+        abort: &Abort,
+    ) -> Result<ExitStatus> {
+        let process = Process {
+            #[allow(clippy::cast_possible_wrap)]
+            pid: child.id() as libc::pid_t,
+            own_group,
+        };
+        abort.register(&self.name, process);
+        // The error is the timeout if the command timed out.
+        let status = match self.timeout() {
+            Some(timeout) => wait_with_timeout(child, process, timeout).map(|s| s.ok_or(timeout)),
+            None => child.wait().map(Ok).map_err(Into::into),
+        };
+        abort.unregister(&self.name);
+
+        match status? {
+            Ok(status) if abort.is_aborted() && !status.success() => {
+                self.append_to_log(&format!(
+                    "==> {} terminated as the run was stopped\n",
+                    command
+                ));
+                bail!(TasksError::CmdAborted {
+                    name: self.name.clone(),
+                    command,
+                    details: self.failure_info(&stderr_tail.lines()),
+                });
+            }
+            Ok(status) => Ok(status),
+            Err(timeout) => {
+                self.append_to_log(&format!("==> {} timed out after {:?}\n", command, timeout));
+                bail!(TasksError::CmdTimedOut {
+                    name: self.name.clone(),
+                    command,
+                    timeout,
+                    details: self.failure_info(&stderr_tail.lines()),
+                });
+            }
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.config.timeout.map(Duration::from_secs)
+    }
+
+    /// Append `text` to the task's log file. Failing to write the log file
+    /// doesn't fail the task.
+    fn append_to_log(&self, text: &str) {
+        if let Some(log_path) = &self.log_path {
+            if let Err(e) = open_log(log_path).and_then(|mut f| f.write_all(text.as_bytes())) {
+                warn!(
+                    "Failed to write to log file '{}': {}",
+                    log_path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Where to find the full output of a failed command, and the end of its
+    /// stderr.
+    fn failure_info(&self, stderr_tail: &VecDeque<String>) -> String {
+        let mut info = String::new();
+        if let Some(log_path) = &self.log_path {
+            let _ = write!(info, "\n  Full output: {}", log_path.display());
+        }
+        if !stderr_tail.is_empty() {
+            let _ = write!(info, "\n  Last {} lines of stderr:", stderr_tail.len());
+            for line in stderr_tail {
+                let _ = write!(info, "\n    {}", line);
+            }
+        }
+        info
     }
 
-    pub fn get_command(cmd: &[String], env: &HashMap<String, String>) -> Result<Command> {
-        // TODO(gib): set current dir.
+    /// Log the command's stdout and stderr line by line as they are written,
+    /// and write them to the task log file. Stdout is only shown with
+    /// `--task-output=full`. With `passthrough` both are written unchanged to
+    /// the terminal instead of being logged.
+    fn stream_output(
+        &self,
+        child: &mut Child,
+        task_output: TaskOutput,
+        passthrough: bool,
+    ) -> OutputReaders {
+        let (stdout_echo, stderr_echo) = if passthrough {
+            (Echo::Stdout, Echo::Stderr)
+        } else {
+            let stdout_level = match task_output {
+                TaskOutput::Full => Level::Info,
+                TaskOutput::Errors => Level::Debug,
+            };
+            (Echo::Log(stdout_level), Echo::Log(Level::Info))
+        };
+        let stderr_tail = StderrTail::default();
+        OutputReaders {
+            stdout: child.stdout.take().map(|stdout| {
+                stream_lines(
+                    self.name.clone(),
+                    stdout,
+                    stdout_echo,
+                    self.log_path.clone(),
+                    None,
+                )
+            }),
+            stderr: child.stderr.take().map(|stderr| {
+                stream_lines(
+                    self.name.clone(),
+                    stderr,
+                    stderr_echo,
+                    self.log_path.clone(),
+                    Some(stderr_tail.clone()),
+                )
+            }),
+            stderr_tail,
+        }
+    }
+
+    pub fn get_command(
+        &self,
+        cmd: &[String],
+        cwd: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<Command> {
         let mut command = Command::new(
-            &cmd.get(0)
-                .ok_or_else(|| anyhow!("Task '{}' command was empty."))?,
+            cmd.first()
+                .ok_or_else(|| anyhow!("Task '{}' command was empty.", &self.name))?,
         );
         command
             .args(cmd.get(1..).unwrap_or(&[]))
+            .current_dir(cwd)
             .env_clear()
             .envs(env.iter())
             .stdin(Stdio::inherit());
@@ -336,8 +1181,12 @@ impl Task {
         // | Run     | passes | `debug` | `debug`       |
         // | Check   | fails  | `info`  | `debug`       |
         // | Run     | fails  | `error` | `error`       |
+        // | Plugin  | any    | `debug` | `debug`       |
+        //
+        // A plugin can exit non-zero without failing, and its failures include
+        // the end of its stderr.
         let (level, stdout_stderr_level) = match (command_type, status.success()) {
-            (_, true) => (Level::Debug, Level::Debug),
+            (_, true) | (CommandType::Plugin(_), false) => (Level::Debug, Level::Debug),
             (CommandType::Run, false) => (Level::Error, Level::Error),
             (CommandType::Check, false) => (Level::Info, Level::Debug),
         };
@@ -369,3 +1218,332 @@ impl Task {
         }
     }
 }
+
+/// Read everything from `pipe` on a background thread, keeping the last lines
+/// read in `tail` if set.
+fn read_in_background<R>(
+    pipe: Option<R>,
+    tail: Option<StderrTail>,
+) -> JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(pipe) = pipe {
+            let mut reader = BufReader::new(pipe);
+            loop {
+                let start = output.len();
+                if reader.read_until(b'\n', &mut output)? == 0 {
+                    break;
+                }
+                if let Some(tail) = &tail {
+                    tail.push(
+                        String::from_utf8_lossy(&output[start..])
+                            .trim_end()
+                            .to_owned(),
+                    );
+                }
+            }
+        }
+        Ok(output)
+    })
+}
+
+/// Where [`stream_lines`] shows the lines it reads.
+#[derive(Debug, Clone, Copy)]
+enum Echo {
+    /// Log them at this level, prefixed with the task name.
+    Log(Level),
+    /// Write them unchanged to our stdout.
+    Stdout,
+    /// Write them unchanged to our stderr.
+    Stderr,
+}
+
+/// Show each line read from `pipe` on a background thread as set by `echo`,
+/// and append it to the log file at `log_path`, keeping the last lines read in
+/// `tail` if set.
+fn stream_lines<R>(
+    name: String,
+    pipe: R,
+    echo: Echo,
+    log_path: Option<PathBuf>,
+    tail: Option<StderrTail>,
+) -> JoinHandle<io::Result<()>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut log_file = log_path.and_then(|path| match open_log(&path) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                warn!("Failed to open log file '{}': {}", path.display(), e);
+                None
+            }
+        });
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            if let Some(f) = &mut log_file {
+                // Opened in append mode, so whole lines from stdout and stderr
+                // don't overwrite each other.
+                f.write_all(&line)?;
+            }
+            let line_str = String::from_utf8_lossy(&line).trim_end().to_owned();
+            // Keep reading (and logging to the file) if the terminal has gone.
+            match echo {
+                Echo::Log(level) => log!(level, "[{}] {}", name, line_str),
+                Echo::Stdout => drop(write_through(&mut io::stdout().lock(), &line)),
+                Echo::Stderr => drop(write_through(&mut io::stderr().lock(), &line)),
+            }
+            if let Some(tail) = &tail {
+                tail.push(line_str);
+            }
+            line.clear();
+        }
+        Ok(())
+    })
+}
+
+/// Write `bytes` to `out` straight away, rather than when its buffer fills.
+fn write_through(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(bytes)?;
+    out.flush()
+}
+
+/// Parse a task file. Files that aren't YAML or JSON are parsed as TOML.
+fn read_config(path: &Path) -> Result<TaskConfig> {
+    let s = fs::read_to_string(path).map_err(|e| TasksError::ReadFile {
+        path: path.to_owned(),
+        source: e,
+    })?;
+    trace!("Task '{:?}' contents: <<<{}>>>", &path, &s);
+    Ok(match path.extension().and_then(OsStr::to_str) {
+        Some("yaml" | "yml") => {
+            serde_yaml::from_str::<TaskConfig>(&s).map_err(|e| TasksError::InvalidYaml {
+                path: path.to_owned(),
+                source: e,
+            })?
+        }
+        Some("json") => {
+            serde_json::from_str::<TaskConfig>(&s).map_err(|e| TasksError::InvalidJson {
+                path: path.to_owned(),
+                source: e,
+            })?
+        }
+        _ => toml::from_str::<TaskConfig>(&s).map_err(|e| TasksError::InvalidToml {
+            path: path.to_owned(),
+            source: e,
+        })?,
+    })
+}
+
+/// Read the config of a task directory, from its config file (if any) and its
+/// `check` and `update` scripts.
+fn read_dir_config(dir: &Path) -> Result<TaskConfig> {
+    let mut config = match DIR_CONFIG_FILES
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.exists())
+    {
+        Some(path) => read_config(&path)?,
+        None => TaskConfig::default(),
+    };
+    for (script, cmd, field) in [
+        (DIR_CHECK_SCRIPT, &mut config.check_cmd, "check_cmd"),
+        (DIR_UPDATE_SCRIPT, &mut config.run_cmd, "run_cmd"),
+    ] {
+        let script_path = dir.join(script);
+        if !script_path.exists() {
+            continue;
+        }
+        if cmd.is_some() {
+            bail!(TasksError::DirCmdConflict {
+                path: dir.to_owned(),
+                script,
+                field,
+            });
+        }
+        *cmd = Some(vec![script_path
+            .to_str()
+            .ok_or(TasksError::None {})?
+            .to_owned()]);
+    }
+    Ok(config)
+}
+
+/// Open a task log file for appending.
+fn open_log(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Wait for `child` to exit, terminating it if it runs for longer than
+/// `timeout`. Returns `None` if the command timed out.
+///
+/// The command is waited for on a separate thread, so we get told as soon as it
+/// exits rather than having to poll it.
+fn wait_with_timeout(
+    mut child: Child,
+    process: Process,
+    timeout: Duration,
+) -> Result<Option<ExitStatus>> {
+    let (exited_tx, exited_rx) = mpsc::channel();
+    let waiter = thread::spawn(move || {
+        let status = child.wait();
+        // Only fails if we've stopped listening.
+        let _ = exited_tx.send(());
+        status
+    });
+    let join_waiter = |waiter: JoinHandle<io::Result<ExitStatus>>| -> Result<ExitStatus> {
+        Ok(waiter
+            .join()
+            .map_err(|_| anyhow!("Failed to wait for command"))??)
+    };
+
+    if exited_rx.recv_timeout(timeout).is_ok() {
+        return Ok(Some(join_waiter(waiter)?));
+    }
+
+    // Ask the command (and anything it started) to exit with SIGTERM, sending
+    // SIGKILL if it is still running after the grace period. The process can
+    // only have been reaped in the instant since the timeout passed, so the
+    // process (group) ID won't have been reused.
+    debug!("Sending SIGTERM to process {:?}", process);
+    process.signal(libc::SIGTERM);
+    if exited_rx.recv_timeout(TERMINATE_GRACE_PERIOD).is_err() {
+        debug!("Sending SIGKILL to process {:?}", process);
+        process.signal(libc::SIGKILL);
+    }
+    join_waiter(waiter)?;
+    Ok(None)
+}
+
+/// Write end of the pipe the signal handler installed by [`catch_signals()`]
+/// writes each signal it catches to, or -1.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Callback passed to [`catch_signals()`].
+type OnSignal = Box<dyn Fn(libc::c_int) + Send>;
+
+/// Called with each signal read from [`SIGNAL_PIPE`].
+static ON_SIGNAL: Mutex<Option<OnSignal>> = Mutex::new(None);
+
+/// Catch SIGINT and SIGTERM, so rather than exiting straight away (leaving the
+/// commands in their own process groups running) the run can be stopped with
+/// [`Abort::abort()`]. `on_signal` is called with each signal caught on a
+/// background thread, replacing any earlier `on_signal`, until the returned
+/// guard is dropped. After that a signal does what it would have done if it
+/// wasn't caught.
+pub(crate) fn catch_signals<F>(on_signal: F) -> SignalGuard
+where
+    F: Fn(libc::c_int) + Send + 'static,
+{
+    static START_READER: Once = Once::new();
+    *ON_SIGNAL.lock().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(on_signal));
+    let mut started = true;
+    START_READER.call_once(|| {
+        if let Err(e) = start_signal_reader() {
+            warn!("Failed to catch signals: {}", e);
+            started = false;
+        }
+    });
+    if started {
+        install_signal_handlers();
+    }
+    SignalGuard { _private: () }
+}
+
+/// Stops passing signals to the `on_signal` given to [`catch_signals()`] when
+/// dropped.
+#[must_use]
+pub(crate) struct SignalGuard {
+    _private: (),
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        *ON_SIGNAL.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+/// Create the pipe the signal handler writes to, and start a thread reading
+/// signals from it and passing them to [`ON_SIGNAL`], as the handler itself
+/// can only do async-signal-safe things.
+fn start_signal_reader() -> io::Result<()> {
+    let mut fds = [0; 2];
+    // Safety: fds has room for the two file descriptors, and we take ownership
+    // of them straight away.
+    let (mut reader, writer) = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))
+    };
+    for fd in fds {
+        // Safety: fcntl has no memory safety requirements.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // So the handler never blocks.
+    // Safety: fcntl has no memory safety requirements.
+    if unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    SIGNAL_PIPE.store(writer.into_raw_fd(), Ordering::SeqCst);
+
+    thread::spawn(move || {
+        let mut byte = [0_u8];
+        while reader.read_exact(&mut byte).is_ok() {
+            let signal = libc::c_int::from(byte[0]);
+            if let Some(on_signal) = &*ON_SIGNAL.lock().unwrap_or_else(PoisonError::into_inner) {
+                on_signal(signal);
+            } else {
+                // No run to stop, so let the signal do what it normally would
+                // (e.g. exit).
+                // Safety: signal and raise have no memory safety requirements.
+                unsafe {
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::raise(signal);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Install a handler for SIGINT and SIGTERM that writes the signal to the
+/// pipe read by [`start_signal_reader()`].
+fn install_signal_handlers() {
+    extern "C" fn handler(signal: libc::c_int) {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let byte = signal as u8;
+        // Safety: write is async-signal-safe. If the pipe is full there are
+        // already signals waiting to be read, so this one can be dropped.
+        unsafe {
+            libc::write(
+                SIGNAL_PIPE.load(Ordering::SeqCst),
+                (&raw const byte).cast(),
+                1,
+            );
+        }
+    }
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // Safety: the handler only calls write, which is async-signal-safe.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&raw mut action.sa_mask);
+            if libc::sigaction(signal, &raw const action, std::ptr::null_mut()) != 0 {
+                warn!(
+                    "Failed to catch signal {}: {}",
+                    signal,
+                    io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
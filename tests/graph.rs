@@ -0,0 +1,70 @@
+use std::process::Command;
+
+/// `up graph` should show what each task requires, and why it runs.
+#[test]
+fn graph_tree() {
+    let mut cmd = graph_cmd("graph_tree", "valid");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Bootstrap order: base
+
+app [auto-run]
+  lib [dependency]
+    base [dependency, bootstrap 1]
+  base [dependency, bootstrap 1]
+extra [not auto-run]
+"
+    );
+}
+
+/// `up graph --format=dot` should print a Graphviz graph.
+#[test]
+fn graph_dot() {
+    let mut cmd = graph_cmd("graph_dot", "valid");
+    cmd.arg("--format=dot");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("digraph tasks {\n"));
+    assert!(stdout.contains(r#""lib" [label="lib\ndependency", style=dashed];"#));
+    assert!(stdout.contains(r#""extra" [label="extra\nnot auto-run", style=dotted];"#));
+    assert!(stdout.contains(r#""app" -> "lib";"#));
+}
+
+/// Cycles and requires of missing tasks should be shown, and fail the command.
+/// Missing tasks are counted (and shown in the DOT graph) once.
+#[test]
+fn graph_invalid() {
+    let mut cmd = graph_cmd("graph_invalid", "invalid");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("    x [auto-run] (cycle)\n"));
+    assert!(stdout.contains("\nCycles:\n- x -> y -> x\n"));
+    assert!(stdout.contains("- bootstrap_tasks includes 'nope'\n"));
+    assert!(stdout.contains("- 'w' requires 'missing'\n"));
+    assert!(stdout.contains("- 'ze\u{301} \"quoted\"' requires 'missing'\n"));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Found 1 requires cycle(s) and 2 missing task(s)."));
+
+    let mut cmd = graph_cmd("graph_invalid_dot", "invalid");
+    cmd.arg("--format=dot");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(r#""x" -> "y" [color=red];"#));
+    assert!(stdout.contains(r#""missing" [label="missing\nnot found", color=red"#));
+    // Required by two tasks, but only one node.
+    assert_eq!(stdout.matches(r#""missing" [label"#).count(), 1);
+    assert!(stdout.contains("\"ze\u{301} \\\"quoted\\\"\" -> \"missing\";"));
+    assert!(stdout.contains("[label=\"ze\u{301} \\\"quoted\\\"\\nauto-run\""));
+}
+
+/// Returns an `up graph` command for a `graph` fixture.
+fn graph_cmd(test_fn: &str, fixture: &str) -> Command {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), test_fn, &format!("graph/{}", fixture));
+    cmd.arg("graph");
+    cmd
+}
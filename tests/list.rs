@@ -0,0 +1,86 @@
+/// `up list --json` should show every task that parses, and every file that
+/// doesn't.
+#[test]
+fn list_json() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "list_json", "list");
+    cmd.args(["list", "--json"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to parse 2 task file(s)."));
+
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let tasks = listing["tasks"].as_array().unwrap();
+    assert_eq!(tasks.len(), 2);
+    let (first, second) = (&tasks[0], &tasks[1]);
+    assert_eq!(first["name"], "first");
+    assert_eq!(first["description"], "Runs first.");
+    assert_eq!(first["run_cmd"][1], "first");
+    assert_eq!(first["auto_run"], true);
+    assert_eq!(first["bootstrap"], true);
+    assert!(first["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("tasks/first.toml"));
+    assert_eq!(second["name"], "second");
+    assert_eq!(second["run_lib"], "link");
+    assert_eq!(second["auto_run"], false);
+    assert_eq!(second["bootstrap"], false);
+    assert_eq!(second["requires"][0], "first");
+    assert_eq!(second["constraints"]["os"], "linux");
+
+    let errors = listing["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("tasks/bad_field.toml"));
+    assert!(errors[0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("unknown field `not_a_field`"));
+    assert!(errors[1]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("tasks/bad_syntax.toml"));
+}
+
+/// YAML and JSON task files that fail to parse should be reported with where
+/// the error is.
+#[test]
+fn list_format_errors() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "list_format_errors", "formats/invalid");
+    cmd.args(["list", "--json"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let errors = listing["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    let yaml_error = errors[0]["error"].as_str().unwrap();
+    assert!(yaml_error.contains("Invalid yaml at '"));
+    assert!(yaml_error.contains("unknown field `not_a_field`"));
+    assert!(yaml_error.contains("line 2 column 1"));
+    let json_error = errors[1]["error"].as_str().unwrap();
+    assert!(json_error.contains("Invalid json at '"));
+    assert!(json_error.contains("bad_syntax.json"));
+    assert!(json_error.contains("line 2 column 23"));
+}
+
+/// `up list` should show a table of tasks, then the files that failed to parse.
+#[test]
+fn list_table() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "list_table", "list");
+    cmd.arg("list");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines[0].starts_with("NAME    RUNS           AUTO_RUN  BOOTSTRAP  REQUIRES"));
+    assert!(lines[1].starts_with("first   echo first     true      true"));
+    assert!(
+        lines[2].starts_with("second  run_lib: link  false     false      first     os = linux")
+    );
+    assert!(lines[2].ends_with("tasks/second.toml  Links things."));
+    assert!(stdout.contains("\nFailed to parse:\n- "));
+    assert!(stdout.contains("bad_field.toml: "));
+}
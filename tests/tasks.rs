@@ -0,0 +1,979 @@
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use testutils::assert;
+
+/// Tasks should wait for the tasks they require, pulling in required tasks that
+/// aren't auto-run.
+#[test]
+fn requires_ordering() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "requires_ordering", "requires/ordering");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/order"), "a\nb\nc\n");
+}
+
+/// Tasks whose requires fail should be skipped, and the run should fail.
+#[test]
+fn requires_failure() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "requires_failure", "requires/failure");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert::nothing_at(&temp_dir.join("up_temp_dir/order"));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Skipping task 'e' as required task 'd' failed."));
+}
+
+/// Cycles in task requires should be an error before anything runs.
+#[test]
+fn requires_cycle() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "requires_cycle", "requires/cycle");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert::nothing_at(&temp_dir.join("up_temp_dir/order"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cycles: x -> y -> x."));
+}
+
+/// Requiring a task that doesn't exist should be an error before anything runs.
+#[test]
+fn requires_unknown() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "requires_unknown", "requires/unknown");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert::nothing_at(&temp_dir.join("up_temp_dir/order"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Task 'w' requires task 'missing', not found in the tasks directory or up.toml."
+    ));
+}
+
+/// Tasks should only run when all their constraints match.
+#[test]
+fn constraints() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "constraints", "constraints");
+    cmd.env("UP_TEST_CONSTRAINT", "yes");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/order"), "matching\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(
+        r#"Skipping task 'env_mismatch' as constraint env.UP_TEST_CONSTRAINT = "no" (found "yes")"#
+    ));
+    assert!(stderr.contains(r#"Skipping task 'negated' as constraint executable = "!sh""#));
+}
+
+/// A typo in a constraint key should be an error when the task is loaded, even
+/// if the task wouldn't be run.
+#[test]
+fn unknown_constraint() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "unknown_constraint", "unknown_constraint");
+    cmd.args(["run", "--tasks", "other"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'typo' constraint 'sytem' isn't one of os,"));
+}
+
+/// Dry runs should run check commands, but not change anything.
+#[test]
+fn dry_run() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "dry_run", "dry_run");
+    cmd.args(["run", "--dry-run"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::nothing_at(&temp_dir.join("up_temp_dir/order"));
+    assert::nothing_at(&temp_dir.join("up_temp_dir/home"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipping task 'checked' as check command passed."));
+    assert!(stderr.contains(&format!(
+        r#"Task 'unchecked' would run: ["sh", "-c", "echo unchecked >> {}/up_temp_dir/order"]"#,
+        temp_dir.display()
+    )));
+    assert!(stderr.contains("Task 'link' would run run_lib 'link' with data"));
+    assert!(stderr.contains("Task 'git' would run run_lib 'git' with data"));
+    assert!(stderr.contains(r#"Tasks that would be skipped: ["checked"]"#));
+}
+
+/// Dry runs should skip run library tasks that are already up to date.
+#[test]
+fn dry_run_up_to_date() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "dry_run_up_to_date", "dry_run");
+    let up_temp_dir = temp_dir.join("up_temp_dir").canonicalize().unwrap();
+    fs::create_dir_all(up_temp_dir.join("dotfiles/.config")).unwrap();
+    fs::write(up_temp_dir.join("dotfiles/.config/rc"), "").unwrap();
+    fs::create_dir_all(up_temp_dir.join("home/.config")).unwrap();
+    std::os::unix::fs::symlink(
+        up_temp_dir.join("dotfiles/.config/rc"),
+        up_temp_dir.join("home/.config/rc"),
+    )
+    .unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(&["-c", "user.name=up", "-c", "user.email=up@example.com"])
+            .args(args)
+            .current_dir(&up_temp_dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q", "origin"]);
+    git(&[
+        "-C",
+        "origin",
+        "commit",
+        "-q",
+        "--allow-empty",
+        "-m",
+        "Initial commit",
+    ]);
+    git(&[
+        "clone",
+        "-q",
+        up_temp_dir.join("origin").to_str().unwrap(),
+        "repo",
+    ]);
+
+    cmd.args(["run", "--dry-run", "--tasks", "link", "git"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Skipping task 'link' as run_lib 'link' is up to date."));
+    assert!(stderr.contains("Skipping task 'git' as run_lib 'git' is up to date."));
+    assert!(!temp_dir.join("up_temp_dir/backup").exists());
+
+    // Real runs don't skip the link task, only dry runs do.
+    let mut real_cmd = testutils::up_cmd(&temp_dir);
+    real_cmd.args(
+        [
+            "-c",
+            testutils::fixtures_dir()
+                .join("dry_run/up.toml")
+                .to_str()
+                .unwrap(),
+            "run",
+            "--tasks",
+            "link",
+        ]
+        .iter(),
+    );
+    let output = testutils::run_cmd(&mut real_cmd);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Skipping task 'link'"));
+    assert!(stderr.contains("Tasks passed: [\"link\"]"));
+
+    // A new commit in the local repo's upstream needs merging.
+    git(&[
+        "-C",
+        "origin",
+        "commit",
+        "-q",
+        "--allow-empty",
+        "-m",
+        "New commit",
+    ]);
+    git(&["-C", "repo", "fetch", "-q"]);
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'git' would run run_lib 'git' with data"));
+}
+
+/// With one job tasks should run one at a time, in a consistent order.
+#[test]
+fn jobs() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "jobs", "jobs");
+    cmd.args(["run", "--jobs", "1"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(
+        &temp_dir.join("up_temp_dir/order"),
+        "start a\nend a\nstart b\nend b\nstart c\nend c\n",
+    );
+}
+
+/// Commands that run for too long should be terminated, along with anything
+/// they started, without stopping other tasks from running.
+#[test]
+fn timeout() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "timeout", "timeout");
+    let now = Instant::now();
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(now.elapsed() < Duration::from_secs(20));
+    assert!(!output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/order"), "fast\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // With where to find its output, and what it wrote to stderr before it
+    // timed out.
+    assert!(stderr.contains(&format!(
+        "Task 'slow_run' run_cmd timed out after 2s.\n  Full output: {}",
+        temp_dir.join("logs").display()
+    )));
+    assert!(stderr.contains("/slow_run.log\n  Last 1 lines of stderr:\n    slow_run started\n"));
+    assert!(stderr.contains("Task 'slow_check' check_cmd timed out after 1s."));
+    assert!(stderr.contains("Task 'background' run_cmd timed out after 1s."));
+
+    // Whatever the timed out command started should have been terminated too.
+    thread::sleep(Duration::from_secs(4).saturating_sub(now.elapsed()));
+    assert!(!temp_dir.join("up_temp_dir/leaked").exists());
+}
+
+/// Interrupting up should stop the run, terminating the running commands and
+/// anything they started (which are in their own process groups, so don't get
+/// the terminal's SIGINT).
+#[test]
+fn interrupt() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "interrupt", "interrupt");
+    let started_path = temp_dir.join("up_temp_dir/started");
+    let now = Instant::now();
+    let mut child = cmd
+        .arg("run")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    while fs::read_to_string(&started_path).map_or(0, |started| started.lines().count()) < 2 {
+        assert!(
+            now.elapsed() < Duration::from_secs(10),
+            "Tasks didn't start."
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    let status = Command::new("kill")
+        .args(&["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    while child.try_wait().unwrap().is_none() {
+        assert!(now.elapsed() < Duration::from_secs(10), "up didn't exit.");
+        thread::sleep(Duration::from_millis(50));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Interrupted by SIGINT, stopping the run"));
+    assert!(stderr.contains("Run stopped by SIGINT, tasks not started: [\"after\"]"));
+    assert!(stderr.contains(&format!(
+        "Task 'first' run_cmd was terminated as the run was stopped.\n  Full output: {}",
+        temp_dir.join("logs").display()
+    )));
+
+    // Whatever the commands started should have been terminated too.
+    thread::sleep(Duration::from_secs(4).saturating_sub(now.elapsed()));
+    assert!(!temp_dir.join("up_temp_dir/leaked").exists());
+    assert!(!fs::read_to_string(&started_path).unwrap().contains("after"));
+}
+
+/// Commands should run in the task directory, or the `cwd` if set.
+#[test]
+fn cwd() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "cwd", "cwd");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let tasks_dir = testutils::fixtures_dir()
+        .join("cwd/tasks")
+        .canonicalize()
+        .unwrap();
+    assert::file(
+        &temp_dir.join("up_temp_dir/default_cwd"),
+        &format!("{}\n", tasks_dir.display()),
+    );
+    assert::file(
+        &temp_dir.join("up_temp_dir/custom_cwd"),
+        &format!("{}\n", temp_dir.join("up_temp_dir").display()),
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "Task 'missing' working directory '{}' doesn't exist.",
+        temp_dir.join("up_temp_dir/missing").display()
+    )));
+}
+
+/// An empty command should fail its task, saying which task it was.
+#[test]
+fn empty_cmd() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "empty_cmd", "empty_cmd");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Task 'empty' command was empty."));
+}
+
+/// A command that can't be started should fail its task, saying which task,
+/// command and working directory it was.
+#[test]
+fn missing_cmd() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "missing_cmd", "missing_cmd");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let tasks_dir = testutils::fixtures_dir()
+        .join("missing_cmd/tasks")
+        .canonicalize()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "Task 'missing' failed to start run_cmd [\"up_test_missing_cmd\"] in '{}'.",
+        tasks_dir.display()
+    )));
+}
+
+/// Task env vars should be layered on top of the global env, without affecting
+/// other tasks.
+#[test]
+fn task_env() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "task_env", "task_env");
+    cmd.env("UP_TEST_TASK_ENV", "inherited");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(
+        &temp_dir.join("up_temp_dir/with_env"),
+        "global-local\ntask-global\ninherited\n",
+    );
+    assert::file(&temp_dir.join("up_temp_dir/without_env"), "global\n");
+}
+
+/// Task output should be streamed with the task name as a prefix.
+#[test]
+fn task_output() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "task_output", "task_output");
+    cmd.args(["run", "--task-output=full"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[printer] stdout line"));
+    assert!(stderr.contains("[printer] stderr line"));
+}
+
+/// Task output should be written to per-task log files, and failures should show
+/// the end of stderr and where to find the full output.
+#[test]
+fn task_logs() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "task_logs", "task_logs");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+
+    // The task log dir is named after the run's log file, and linked to like it.
+    let log_dir = temp_dir.join("logs");
+    let task_log_dir = log_dir.join(fs::read_link(log_dir.join("up-rs_latest_tasks")).unwrap());
+    let run_log = fs::read_link(log_dir.join("up-rs_latest.log")).unwrap();
+    assert_eq!(
+        task_log_dir,
+        run_log.with_file_name(format!(
+            "{}_tasks",
+            run_log.file_stem().unwrap().to_str().unwrap()
+        ))
+    );
+    let log_path = task_log_dir.join("failing.log");
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("checking\n"));
+    assert!(log.contains("run stdout\n"));
+    assert!(log.contains("stderr line 1\n"));
+    assert!(log.contains("==> run_cmd finished with exit status: 3"));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'failing' run_cmd failed with exit status: 3."));
+    assert!(stderr.contains(&format!("Full output: {}", log_path.display())));
+    assert!(stderr.contains("Last 10 lines of stderr:\n    stderr line 3\n"));
+    assert!(stderr.contains("    stderr line 12"));
+    assert!(!stderr.contains("    stderr line 2\n"));
+}
+
+/// A task run on its own has its output passed through to the terminal, and
+/// should still have it written to its task log.
+#[test]
+fn task_logs_passthrough() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "task_logs_passthrough", "task_logs");
+    cmd.args(["run", "--tasks", "failing"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+
+    let log_path = temp_dir.join("logs/up-rs_latest_tasks/failing.log");
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("run stdout\n"));
+    assert!(log.contains("stderr line 12\n"));
+
+    // Passed through without the task name prefix.
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "run stdout\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\nstderr line 1\n"));
+    assert!(!stderr.contains("[failing] stderr line 1\n"));
+    assert!(stderr.contains("Last 10 lines of stderr:\n    stderr line 3\n"));
+}
+
+/// With the live display shown, the last task should still get the terminal
+/// for its input and output, with the display cleared while it runs.
+#[test]
+fn passthrough_progress() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "passthrough_progress", "passthrough_progress");
+    // So the display can be matched without its colours.
+    cmd.args(["run"].iter()).env("CLICOLOR", "0");
+    let output = testutils::run_cmd_in_pty(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "first\ntty\n");
+    let log = fs::read_to_string(temp_dir.join("logs/up-rs_latest_tasks/last.log")).unwrap();
+    assert!(log.contains("Output went straight to the terminal"));
+    // The display was drawn, and cleared.
+    let terminal = String::from_utf8_lossy(&output.stderr);
+    assert!(terminal.contains("  first passed"));
+    assert!(terminal.contains("\u{1b}[2K"));
+}
+
+/// `--report` should write a JSON report with the result of each task.
+#[test]
+fn report() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "report", "report");
+    let report_path = temp_dir.join("report.json");
+    cmd.args(["run", "--report", report_path.to_str().unwrap()].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+    assert_eq!(report["dry_run"], false);
+    let tasks = report["tasks"].as_array().unwrap();
+    let names: Vec<&str> = tasks.iter().map(|t| t["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["fail", "pass", "skip"]);
+
+    let (fail, pass, skip) = (&tasks[0], &tasks[1], &tasks[2]);
+    assert_eq!(fail["status"], "failed");
+    assert_eq!(fail["exit_code"], 2);
+    assert!(fail["run_duration_secs"].is_f64());
+    assert!(fail["errors"][0]
+        .as_str()
+        .unwrap()
+        .starts_with("Task 'fail' run_cmd failed with exit status: 2."));
+    assert!(fail["path"].as_str().unwrap().ends_with("tasks/fail.toml"));
+    assert_eq!(pass["status"], "passed");
+    assert_eq!(pass["exit_code"], 0);
+    assert_eq!(pass["run_cmd"][0], "true");
+    assert_eq!(pass["errors"].as_array().unwrap().len(), 0);
+    assert_eq!(skip["status"], "skipped");
+    assert!(skip["check_duration_secs"].is_f64());
+    assert!(skip["run_duration_secs"].is_null());
+    assert!(skip["exit_code"].is_null());
+}
+
+/// `--report` should still be written, with the error, if the run fails before
+/// any task is started.
+#[test]
+fn report_error() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "report_error", "requires/cycle");
+    let report_path = temp_dir.join("report.json");
+    cmd.args(["run", "--report", report_path.to_str().unwrap()].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+    assert_eq!(report["tasks"].as_array().unwrap().len(), 0);
+    assert_eq!(report["not_started"].as_array().unwrap().len(), 0);
+    assert!(report["errors"][0]
+        .as_str()
+        .unwrap()
+        .contains("cycles: x -> y -> x."));
+}
+
+/// Failed run commands and run libraries should be retried up to `retries` times.
+#[test]
+fn retries() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "retries", "retries");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/attempts"), "xxx");
+    assert::file(&temp_dir.join("up_temp_dir/broken"), "broken\nbroken\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'flaky' failed (attempt 2 of 3), retrying in 0ns"));
+    assert!(stderr.contains("Task 'broken' failed (attempt 1 of 2), retrying in 1s"));
+    assert!(stderr.contains("Task 'broken' failed after 2 attempts."));
+    assert!(stderr.contains("Task 'broken_lib' failed after 2 attempts."));
+    assert!(!stderr.contains("Task 'flaky' failed after"));
+}
+
+/// The `check` section should control how the check command output is judged.
+#[test]
+fn check() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "check", "check");
+    cmd.args(["run", "--jobs", "1"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(
+        &temp_dir.join("up_temp_dir/ran"),
+        "inverted_run\nregex_mismatch\n",
+    );
+}
+
+/// A `check` section without a `check_cmd` would never be used, so it should be
+/// an error rather than the task always running.
+#[test]
+fn check_without_cmd() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "check_without_cmd", "check_without_cmd");
+    cmd.args(["run"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'typo' has a check table but no check_cmd"));
+}
+
+/// An invalid check `stdout_regex` should be an error when the task is loaded,
+/// even if the task wouldn't be run.
+#[test]
+fn invalid_check_regex() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "invalid_check_regex", "invalid_check_regex");
+    cmd.args(["run", "--tasks", "other"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'bad_regex' check has an invalid stdout_regex '('."));
+}
+
+/// With `--fail-fast` the first failure should terminate running tasks and
+/// stop any more from starting.
+#[test]
+fn fail_fast() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "fail_fast", "fail_fast");
+    cmd.args(["run", "--fail-fast", "--tasks", "fail", "slow", "after"].iter());
+    let now = Instant::now();
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(now.elapsed() < Duration::from_secs(3));
+    assert!(!output.status.success());
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'slow' run_cmd was terminated as the run was stopped."));
+    assert!(stderr.contains("Run stopped as task 'fail' failed, tasks not started: [\"after\"]"));
+}
+
+/// Stopping the run should cut short any retry delay, without retrying.
+#[test]
+fn fail_fast_retry_delay() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "fail_fast_retry_delay", "fail_fast");
+    cmd.args(["run", "--fail-fast", "--tasks", "retrying", "fail_later"].iter());
+    let now = Instant::now();
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(now.elapsed() < Duration::from_secs(10));
+    assert!(!output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/attempts"), "attempt\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Task 'retrying' failed (attempt 1 of 2), retrying in 30s"));
+    assert!(!stderr.contains("Retrying task 'retrying'"));
+}
+
+/// A failing `critical` task should stop the run, unless `--keep-going` is
+/// passed.
+#[test]
+fn critical() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "critical", "fail_fast");
+    cmd.args(["run", "--tasks", "critical_fail", "slow", "after"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Run stopped as task 'critical_fail' failed"));
+
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "critical_keep_going", "fail_fast");
+    cmd.args(
+        [
+            "run",
+            "--keep-going",
+            "--tasks",
+            "critical_fail",
+            "slow",
+            "after",
+        ]
+        .iter(),
+    );
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "slow\nafter\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Run stopped"));
+}
+
+/// Sudo should only be prompted for if a task that will be run needs it.
+#[test]
+fn needs_sudo() {
+    let fake_sudo_dir = testutils::fixtures_dir().join("needs_sudo/bin");
+    let path = format!(
+        "{}:{}",
+        fake_sudo_dir.display(),
+        std::env::var("PATH").unwrap()
+    );
+
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "needs_sudo_unselected", "needs_sudo");
+    cmd.env("PATH", &path);
+    cmd.args(["run", "--tasks", "no_sudo", "wrong_os"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::nothing_at(&temp_dir.join("up_temp_dir/sudo_calls"));
+
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "needs_sudo", "needs_sudo");
+    cmd.env("PATH", &path);
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/sudo_calls"), "-v\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Tasks [\"already_done\", \"needs_sudo\"] need superuser privileges."));
+    assert!(stderr.contains("Tasks run with sudo: [\"needs_sudo\"]"));
+}
+
+/// A task that needs sudo but passed within its `run_interval` will be skipped,
+/// so shouldn't prompt for a password.
+#[test]
+fn needs_sudo_run_interval() {
+    let fake_sudo_dir = testutils::fixtures_dir().join("needs_sudo/bin");
+    let path = format!(
+        "{}:{}",
+        fake_sudo_dir.display(),
+        std::env::var("PATH").unwrap()
+    );
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "needs_sudo_run_interval", "needs_sudo_interval");
+    cmd.env("PATH", &path);
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    let sudo_calls = temp_dir.join("up_temp_dir/sudo_calls");
+    assert::file(&sudo_calls, "-v\n");
+    fs::remove_file(&sudo_calls).unwrap();
+
+    let up_toml = testutils::fixtures_dir().join("needs_sudo_interval/up.toml");
+    let mut cmd = testutils::up_cmd(&temp_dir);
+    cmd.env("PATH", &path);
+    cmd.args(["-c", up_toml.to_str().unwrap(), "run"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipping task 'daily' as it ran "));
+    assert::nothing_at(&sudo_calls);
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "daily\n");
+}
+
+/// Once superuser privileges can't be refreshed, tasks that need them shouldn't
+/// be started, as they would wait for a password.
+#[test]
+fn needs_sudo_expired() {
+    let fake_sudo_dir = testutils::fixtures_dir().join("needs_sudo/bin");
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "needs_sudo_expired", "needs_sudo_expired");
+    cmd.env(
+        "PATH",
+        format!(
+            "{}:{}",
+            fake_sudo_dir.display(),
+            std::env::var("PATH").unwrap()
+        ),
+    );
+    cmd.env("FAKE_SUDO_EXPIRED", "1");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "first\n");
+    assert!(fs::read_to_string(temp_dir.join("up_temp_dir/sudo_calls"))
+        .unwrap()
+        .starts_with("-v\n-n -v\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(
+        "Failed to refresh superuser privileges, 'sudo -n -v' failed with exit status: 1: sudo: \
+         a password is required"
+    ));
+    assert!(stderr
+        .contains("Not starting task 'second' as superuser privileges expired: Failed to refresh"));
+    assert!(stderr.contains("Tasks run with sudo: [\"first\"]"));
+}
+
+/// Tasks that passed within their `run_interval` should be skipped, unless
+/// `--force` is passed.
+#[test]
+fn run_interval() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "run_interval", "run_interval");
+    let up_toml = testutils::fixtures_dir().join("run_interval/up.toml");
+    let rerun_cmd = |args: &[&str]| {
+        let mut cmd = testutils::up_cmd(&temp_dir);
+        cmd.args(["-c", up_toml.to_str().unwrap(), "run", "--jobs", "1"].iter());
+        cmd.args(args);
+        cmd
+    };
+
+    cmd.args(["run", "--jobs", "1"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "always\ndaily\n");
+    let state = fs::read_to_string(temp_dir.join("up_state_dir/up-rs/state.json")).unwrap();
+    assert!(state.contains("\"outcome\": \"passed\""));
+
+    let output = testutils::run_cmd(&mut rerun_cmd(&[]));
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "always\ndaily\nalways\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipping task 'daily' as it ran "));
+
+    let output = testutils::run_cmd(&mut rerun_cmd(&["--force"]));
+    assert!(output.status.success());
+    assert::file(
+        &temp_dir.join("up_temp_dir/ran"),
+        "always\ndaily\nalways\nalways\ndaily\n",
+    );
+}
+
+/// A run whose check skipped the task shouldn't reset when it last passed, and
+/// the state file should be keyed by tasks dir and replaced in one go.
+#[test]
+fn run_interval_check() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "run_interval_check", "run_interval_check");
+    let up_toml = testutils::fixtures_dir().join("run_interval_check/up.toml");
+    let rerun_cmd = |args: &[&str]| {
+        let mut cmd = testutils::up_cmd(&temp_dir);
+        cmd.args(["-c", up_toml.to_str().unwrap(), "run"].iter());
+        cmd.args(args);
+        cmd
+    };
+
+    cmd.args(["run"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "daily\n");
+
+    let output = testutils::run_cmd(&mut rerun_cmd(&["--force"]));
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Tasks skipped: [\"daily\"]"));
+    let state_dir = temp_dir.join("up_state_dir/up-rs");
+    let state = fs::read_to_string(state_dir.join("state.json")).unwrap();
+    assert!(state.contains("\"outcome\": \"skipped\""));
+    assert!(state.contains("\"last_passed\": \""));
+    let tasks_dir = testutils::fixtures_dir().join("run_interval_check/tasks");
+    assert!(state.contains(&format!("\"{}\": {{", tasks_dir.display())));
+    let mut state_files: Vec<_> = fs::read_dir(&state_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    state_files.sort();
+    assert_eq!(state_files, ["state.json", "state.json.lock"]);
+
+    let output = testutils::run_cmd(&mut rerun_cmd(&[]));
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipping task 'daily' as it ran "));
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "daily\n");
+}
+
+/// A run saving its state shouldn't drop what another run saved while it was
+/// running.
+#[test]
+fn run_interval_concurrent() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(
+        file!(),
+        "run_interval_concurrent",
+        "run_interval_concurrent",
+    );
+    let started_path = temp_dir.join("up_temp_dir/started");
+    let now = Instant::now();
+    let slow_run = cmd
+        .arg("run")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    while !started_path.exists() {
+        assert!(
+            now.elapsed() < Duration::from_secs(10),
+            "Task didn't start."
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let mut cmd = testutils::up_cmd(&temp_dir);
+    let up_toml = testutils::fixtures_dir().join("run_interval/up.toml");
+    cmd.args(["-c", up_toml.to_str().unwrap(), "run"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+
+    let output = slow_run.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let state = fs::read_to_string(temp_dir.join("up_state_dir/up-rs/state.json")).unwrap();
+    for fixture in ["run_interval", "run_interval_concurrent"] {
+        let tasks_dir = testutils::fixtures_dir().join(fixture).join("tasks");
+        assert!(
+            state.contains(&format!("\"{}\": {{", tasks_dir.display())),
+            "state: {}",
+            state
+        );
+    }
+}
+
+/// Task files can be TOML, YAML or JSON.
+#[test]
+fn formats() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "formats", "formats/valid");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "toml\nyaml\nyml\njson\n");
+}
+
+/// A directory with `check` and `update` scripts is a task, configured by an
+/// optional `task.toml`. Other directories are ignored.
+#[test]
+fn dir_tasks() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "dir_tasks", "dir_tasks");
+    cmd.args(["run", "--jobs", "1"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "hello\n");
+}
+
+/// Tasks can be defined in the up.toml as a `[[tasks]]` array, alongside task
+/// files.
+#[test]
+fn inline_tasks_list() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "inline_tasks_list", "inline_tasks/list");
+    cmd.args(["run", "--jobs", "1"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(
+        &temp_dir.join("up_temp_dir/ran"),
+        "first\nfrom_file\nsecond\n",
+    );
+}
+
+/// Tasks can be defined in the up.toml as `[tasks.<name>]` tables, without a
+/// tasks directory.
+#[test]
+fn inline_tasks_named() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "inline_tasks_named", "inline_tasks/named");
+    cmd.args(["run", "--jobs", "1"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(&temp_dir.join("up_temp_dir/ran"), "first\nsecond\n");
+}
+
+/// A task defined twice, in a file and inline or in two `[[tasks]]` entries, is
+/// an error naming both definitions.
+#[test]
+fn inline_tasks_duplicate() {
+    let (temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "inline_tasks_duplicate", "inline_tasks/duplicate");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let fixture_dir = testutils::fixtures_dir().join("inline_tasks/duplicate");
+    assert!(
+        stderr.contains(&format!(
+            "Task 'foo' is defined twice, in '{}' and in '{}' [tasks.foo].",
+            fixture_dir.join("tasks/foo.toml").display(),
+            fixture_dir.join("up.toml").display(),
+        )),
+        "stderr: {}",
+        stderr
+    );
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(
+        file!(),
+        "inline_tasks_duplicate_list",
+        "inline_tasks/duplicate_list",
+    );
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let up_toml = testutils::fixtures_dir().join("inline_tasks/duplicate_list/up.toml");
+    assert!(
+        stderr.contains(&format!(
+            "Task 'foo' is defined twice, in '{0}' [[tasks]] entry 1 and in '{0}' [[tasks]] entry 2.",
+            up_toml.display(),
+        )),
+        "stderr: {}",
+        stderr
+    );
+    assert!(!temp_dir.join("up_temp_dir/ran").exists());
+}
+
+/// A `run_lib` that isn't registered should fail, listing the ones that are.
+#[test]
+fn run_lib_unknown() {
+    let (_temp_dir, mut cmd) =
+        testutils::fixture_cmd(file!(), "run_lib_unknown", "run_lib_unknown");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "No run library or up-mystery plugin for task 'mystery', registered libraries: \
+             defaults, generate_git, git, link, self."
+        ),
+        "stderr: {}",
+        stderr
+    );
+}
+
+/// A `run_lib` that isn't registered should run the `up-<name>` plugin with the
+/// task's data, skipping the run if the plugin's check finds nothing to change.
+#[test]
+fn run_lib_plugin() {
+    let (temp_dir, mut cmd) = testutils::fixture_cmd(file!(), "run_lib_plugin", "plugins");
+    let up_toml = testutils::fixtures_dir().join("plugins/up.toml");
+    let rerun_cmd = |args: &[&str]| {
+        let mut cmd = testutils::up_cmd(&temp_dir);
+        cmd.args(["-c", up_toml.to_str().unwrap(), "run"].iter());
+        cmd.args(args);
+        cmd
+    };
+
+    cmd.args(["run", "--tasks", "record"].iter());
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert::file(
+        &temp_dir.join("up_temp_dir/record"),
+        &format!(
+            r#"{{"dir":"{}","list":[1,"x"]}}"#,
+            temp_dir.join("up_temp_dir").display()
+        ),
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Task 'record' plugin run: Recorded."));
+
+    let output = testutils::run_cmd(&mut rerun_cmd(&["--tasks", "record"]));
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Skipping task 'record' as plugin check found nothing to change."));
+
+    let output = testutils::run_cmd(&mut rerun_cmd(&["--tasks", "broken"]));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Task 'broken' plugin '{}' failed in check mode with exit status: 3.",
+            testutils::fixtures_dir()
+                .join("plugins/plugins/up-broken")
+                .display()
+        )),
+        "stderr: {}",
+        stderr
+    );
+    assert!(stderr.contains("broken plugin ran in check mode"));
+}
+
+/// Tasks that write more than fits in a pipe buffer shouldn't block.
+#[test]
+fn large_output() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "large_output", "large_output");
+    let output = testutils::run_cmd_with_deadline(&mut cmd, Duration::from_secs(20));
+    assert!(output.status.success());
+}
+
+/// If there is only one task its output should go straight to the terminal.
+#[test]
+fn passthrough() {
+    let (_, mut cmd) = testutils::fixture_cmd(file!(), "passthrough", "passthrough");
+    let output = testutils::run_cmd(&mut cmd);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "passthrough stdout\n"
+    );
+}
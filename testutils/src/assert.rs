@@ -2,11 +2,11 @@ use std::{fs, path::Path};
 
 /// Panic if there is a file, directory, or link at the path.
 pub fn nothing_at(path: &Path) {
-    assert!(!path.exists(), "Path '{:?}' shouldn't exist.", &path);
+    assert!(!path.exists(), "Path '{:?}' shouldn't exist.", path);
     assert!(
         path.symlink_metadata().is_err(),
         "Path '{:?}' should not be a symlink, but found: '{:?}'.",
-        &path,
+        path,
         path.symlink_metadata().unwrap()
     );
 }
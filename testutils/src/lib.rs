@@ -1,10 +1,15 @@
 //! Common functions that are used by other tests.
 
 use std::{
-    env, fs,
-    os::unix,
+    env,
+    fs::{self, File},
+    io::Read,
+    os::unix::{self, io::FromRawFd},
     path::{Path, PathBuf},
-    process::{Command, Output},
+    process::{Command, Output, Stdio},
+    ptr,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
@@ -42,6 +47,8 @@ pub fn up_cmd(temp_dir: &Path) -> Command {
     let mut cmd = Command::new(up_binary_dir().join("up"));
     // Set temp dir to be inside our test's temp dir.
     cmd.env("TMPDIR", temp_dir.join("up_temp_dir"));
+    // Keep task state (e.g. when tasks last ran) inside our test's temp dir.
+    cmd.env("XDG_STATE_HOME", temp_dir.join("up_state_dir"));
     // Always print colours, even when output is not a tty.
     cmd.env("RUST_LOG_STYLE", "always");
     // Show backtrace on exit, nightly only for now.
@@ -59,12 +66,147 @@ pub fn up_cmd(temp_dir: &Path) -> Command {
     cmd
 }
 
+/// Returns an `up` command using the `up.toml` in `fixture` (relative to the
+/// fixtures dir), and the temp dir for test `test_fn` in test file `file`. The
+/// temp dir contains an empty `up_temp_dir` (the `TMPDIR` for the command).
+///
+/// # Panics
+///
+/// Panics if the temp dir can't be created.
+#[must_use]
+pub fn fixture_cmd(file: &str, test_fn: &str, fixture: &str) -> (PathBuf, Command) {
+    let temp_dir = temp_dir(file, test_fn).unwrap();
+    fs::create_dir(temp_dir.join("up_temp_dir")).unwrap();
+    let up_toml = fixtures_dir().join(fixture).join("up.toml");
+    let mut cmd = up_cmd(&temp_dir);
+    cmd.args(["-c", up_toml.to_str().unwrap()].iter());
+    (temp_dir, cmd)
+}
+
 /// Runs a command and prints out the stdout/stderr nicely.
 /// Returns the command output.
 #[must_use]
 pub fn run_cmd(cmd: &mut Command) -> Output {
     println!("Running command '{:?}'.", cmd);
     let cmd_output = cmd.output().unwrap();
+    print_output(&cmd_output);
+    cmd_output
+}
+
+/// Like [`run_cmd`], but kills the command and panics if it hasn't finished
+/// within `deadline`, so a test for a hang fails rather than hanging itself.
+///
+/// # Panics
+///
+/// Panics if the command can't be run, or runs for longer than `deadline`.
+#[must_use]
+pub fn run_cmd_with_deadline(cmd: &mut Command, deadline: Duration) -> Output {
+    println!("Running command '{:?}' (deadline {:?}).", cmd, deadline);
+    let start = Instant::now();
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Read output in the background so the command can't block on a full pipe.
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            panic!(
+                "Command didn't finish within {:?}, killed it.\n  stderr:\n\n{}",
+                deadline,
+                String::from_utf8_lossy(&stderr.join().unwrap())
+            );
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let cmd_output = Output {
+        status,
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+    };
+    print_output(&cmd_output);
+    cmd_output
+}
+
+/// Like [`run_cmd`], but with the command's stdin, stdout and stderr on a
+/// pseudo terminal, so it behaves as if run interactively (e.g. shows its live
+/// display). The returned stderr is everything written to the terminal, and
+/// stdout is empty.
+///
+/// # Panics
+///
+/// Panics if the terminal can't be opened or the command can't be run.
+#[must_use]
+pub fn run_cmd_in_pty(cmd: &mut Command) -> Output {
+    println!("Running command '{:?}' in a pty.", cmd);
+    let (mut controller, mut terminal) = (0, 0);
+    let size = libc::winsize {
+        ws_row: 40,
+        ws_col: 120,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // Safety: the pointers are valid for the call, and we take ownership of the
+    // returned file descriptors.
+    let (controller, terminal) = unsafe {
+        assert_eq!(
+            libc::openpty(
+                &mut controller,
+                &mut terminal,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &size,
+            ),
+            0
+        );
+        (File::from_raw_fd(controller), File::from_raw_fd(terminal))
+    };
+    let child = cmd
+        .stdin(terminal.try_clone().unwrap())
+        .stdout(terminal.try_clone().unwrap())
+        .stderr(terminal)
+        .spawn()
+        .unwrap();
+    // Close our copies of the terminal, so reading it ends when the command exits.
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let terminal_output = thread::spawn(move || {
+        let mut controller = controller;
+        let mut buf = Vec::new();
+        // Fails with EIO on Linux once nothing has the terminal open.
+        let _ = controller.read_to_end(&mut buf);
+        buf
+    });
+    let output = child.wait_with_output().unwrap();
+    let cmd_output = Output {
+        stderr: terminal_output.join().unwrap(),
+        ..output
+    };
+    print_output(&cmd_output);
+    cmd_output
+}
+
+/// Read all of `pipe` in a background thread.
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf).unwrap();
+        }
+        buf
+    })
+}
+
+/// Print a command's status, stdout, and stderr.
+fn print_output(cmd_output: &Output) {
     println!("  status: {}", cmd_output.status);
     if !cmd_output.stdout.is_empty() {
         println!("  stdout: {}", String::from_utf8_lossy(&cmd_output.stdout));
@@ -75,7 +217,6 @@ pub fn run_cmd(cmd: &mut Command) -> Output {
             String::from_utf8_lossy(&cmd_output.stderr)
         );
     }
-    cmd_output
 }
 
 /// Returns the test module name (usually the test file name).
@@ -120,7 +261,7 @@ pub fn temp_dir(file: &str, test_fn: &str) -> Result<PathBuf> {
 /// Fails if any of the underlying file system operations fail.
 pub fn copy_all(from_dir: &Path, to_dir: &Path) -> Result<()> {
     println!("Copying everything in '{:?}' to '{:?}'", from_dir, to_dir);
-    for from_path in WalkDir::new(&from_dir)
+    for from_path in WalkDir::new(from_dir)
         .min_depth(1)
         .into_iter()
         .filter_map(Result::ok)
@@ -128,8 +269,8 @@ pub fn copy_all(from_dir: &Path, to_dir: &Path) -> Result<()> {
         let from_path_metadata = from_path.metadata()?;
         let from_path = from_path.path();
 
-        let rel_path = from_path.strip_prefix(&from_dir)?;
-        println!("Copying: {:?}", &rel_path);
+        let rel_path = from_path.strip_prefix(from_dir)?;
+        println!("Copying: {:?}", rel_path);
         let to_path = to_dir.join(rel_path);
 
         let file_type = from_path_metadata.file_type();
@@ -137,7 +278,7 @@ pub fn copy_all(from_dir: &Path, to_dir: &Path) -> Result<()> {
         if file_type.is_dir() {
             fs::create_dir(to_path)?;
         } else if file_type.is_symlink() {
-            unix::fs::symlink(fs::read_link(&from_path)?, to_path)?;
+            unix::fs::symlink(fs::read_link(from_path)?, to_path)?;
         } else if file_type.is_file() {
             fs::copy(from_path, to_path)?;
         }